@@ -0,0 +1,998 @@
+use std::collections::BTreeMap;
+use std::collections::HashSet;
+use std::fmt;
+use std::panic::catch_unwind;
+use std::panic::AssertUnwindSafe;
+use std::sync::atomic::fence;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+#[cfg(feature = "with_crash_report")]
+use std::sync::TryLockError;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+use super::history::ActivityRecord;
+use super::history::ThreadHistory;
+use super::inspect::panic_message;
+use super::latency::LatencySummary;
+use super::latency::ThreadLatencies;
+use super::status::StatusConfig;
+#[cfg(feature = "with_trace")]
+use super::trace;
+#[cfg(feature = "with_trace")]
+use super::trace::ThreadTrace;
+use super::utilization::ThreadUtilization;
+use super::utilization::Utilization;
+
+lazy_static::lazy_static! {
+    static ref INTERNED_NAMES: Mutex<HashSet<&'static str>> = Mutex::new(HashSet::new());
+}
+
+/// Activity description that can be reported without allocations.
+///
+/// Names are either `&'static str`s or strings interned with [`ActivityName::intern`].
+/// See [`ThreadScope::static_activity`] for details.
+///
+/// [`ActivityName::intern`]: struct.ActivityName.html#method.intern
+/// [`ThreadScope::static_activity`]: struct.ThreadScope.html#method.static_activity
+#[derive(Clone, Copy, Eq, PartialEq, Hash, Debug)]
+pub struct ActivityName(&'static str);
+
+impl ActivityName {
+    /// Intern an activity description created at runtime.
+    ///
+    /// Interned names are never freed but each distinct name is stored only once.
+    /// Names should therefore be interned ahead of time and not for every activity.
+    pub fn intern(name: &str) -> ActivityName {
+        let mut interned = INTERNED_NAMES
+            .lock()
+            .expect("global INTERNED_NAMES lock poisoned");
+        if let Some(name) = interned.get(name) {
+            return ActivityName(name);
+        }
+        let name: &'static str = Box::leak(name.to_string().into_boxed_str());
+        interned.insert(name);
+        ActivityName(name)
+    }
+
+    /// Description of the activity.
+    pub fn as_str(&self) -> &'static str {
+        self.0
+    }
+}
+
+impl From<&'static str> for ActivityName {
+    fn from(name: &'static str) -> ActivityName {
+        ActivityName(name)
+    }
+}
+
+/// Domain types that threads can report as their activity.
+///
+/// Values are stored as they are reported and only rendered when a snapshot of
+/// registered threads is requested, so formatting costs are paid by the reader.
+/// See [`ThreadScope::typed_activity`] for details, including the cases where
+/// activities are also rendered by the reporting thread.
+///
+/// Panics in `describe` and `fields` are caught and reported in the activity description.
+///
+/// ```
+/// use humthreads::ActivityFields;
+/// use humthreads::ActivityReport;
+///
+/// enum Activity {
+///     Polling { partition: i64 },
+///     Committing,
+/// }
+///
+/// impl ActivityReport for Activity {
+///     fn describe(&self) -> String {
+///         match self {
+///             Activity::Polling { partition } => format!("polling partition {}", partition),
+///             Activity::Committing => "committing offsets".into(),
+///         }
+///     }
+///
+///     fn fields(&self) -> ActivityFields {
+///         let mut fields = ActivityFields::new();
+///         if let Activity::Polling { partition } = self {
+///             fields.insert("partition".into(), (*partition).into());
+///         }
+///         fields
+///     }
+/// }
+/// ```
+///
+/// [`ThreadScope::typed_activity`]: struct.ThreadScope.html#method.typed_activity
+pub trait ActivityReport: Send + Sync + 'static {
+    /// Human readable description of the activity.
+    fn describe(&self) -> String;
+
+    /// Structured fields attached to the activity.
+    fn fields(&self) -> ActivityFields {
+        ActivityFields::new()
+    }
+}
+
+/// Structured key-value fields attached to an activity.
+pub type ActivityFields = BTreeMap<String, ActivityValue>;
+
+/// Typed value of an activity field.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ActivityValue {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Str(String),
+}
+
+impl fmt::Display for ActivityValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ActivityValue::Bool(value) => fmt::Display::fmt(value, f),
+            ActivityValue::Int(value) => fmt::Display::fmt(value, f),
+            ActivityValue::Float(value) => fmt::Display::fmt(value, f),
+            ActivityValue::Str(value) => fmt::Display::fmt(value, f),
+        }
+    }
+}
+
+impl From<bool> for ActivityValue {
+    fn from(value: bool) -> ActivityValue {
+        ActivityValue::Bool(value)
+    }
+}
+
+impl From<f32> for ActivityValue {
+    fn from(value: f32) -> ActivityValue {
+        ActivityValue::Float(value.into())
+    }
+}
+
+impl From<f64> for ActivityValue {
+    fn from(value: f64) -> ActivityValue {
+        ActivityValue::Float(value)
+    }
+}
+
+impl From<i32> for ActivityValue {
+    fn from(value: i32) -> ActivityValue {
+        ActivityValue::Int(value.into())
+    }
+}
+
+impl From<i64> for ActivityValue {
+    fn from(value: i64) -> ActivityValue {
+        ActivityValue::Int(value)
+    }
+}
+
+impl From<u32> for ActivityValue {
+    fn from(value: u32) -> ActivityValue {
+        ActivityValue::Int(value.into())
+    }
+}
+
+impl From<&str> for ActivityValue {
+    fn from(value: &str) -> ActivityValue {
+        ActivityValue::Str(value.to_string())
+    }
+}
+
+impl From<String> for ActivityValue {
+    fn from(value: String) -> ActivityValue {
+        ActivityValue::Str(value)
+    }
+}
+
+/// Public view of an activity in a thread's activity stack.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct ActivityFrame {
+    /// Description of the activity.
+    pub activity: String,
+
+    /// Structured fields attached to the activity.
+    #[serde(default)]
+    pub fields: ActivityFields,
+
+    /// Time the activity was reported.
+    pub since: SystemTime,
+}
+
+impl ActivityFrame {
+    pub(crate) fn new(activity: String, fields: ActivityFields) -> ActivityFrame {
+        ActivityFrame {
+            activity,
+            fields,
+            since: SystemTime::now(),
+        }
+    }
+
+    fn overlay(name: ActivityName, since: SystemTime) -> ActivityFrame {
+        ActivityFrame {
+            activity: name.as_str().to_string(),
+            fields: ActivityFields::new(),
+            since,
+        }
+    }
+}
+
+/// Collect activity fields from an iterator of key-value pairs.
+pub(crate) fn collect_fields<F, K, V>(fields: F) -> ActivityFields
+where
+    F: IntoIterator<Item = (K, V)>,
+    K: Into<String>,
+    V: Into<ActivityValue>,
+{
+    fields
+        .into_iter()
+        .map(|(key, value)| (key.into(), value.into()))
+        .collect()
+}
+
+/// Activity reported in place of typed activities that can't be rendered.
+#[cfg(feature = "with_crash_report")]
+const TYPED_PLACEHOLDER: &str = "(typed activity)";
+
+/// Activity as stored in the stack, before it is rendered for snapshots.
+#[derive(Clone)]
+pub(crate) enum StackFrame {
+    Rendered(ActivityFrame),
+    Typed {
+        activity: Arc<dyn ActivityReport>,
+        since: SystemTime,
+    },
+}
+
+impl StackFrame {
+    /// Store a typed activity to render when needed.
+    pub(crate) fn typed(activity: Arc<dyn ActivityReport>) -> StackFrame {
+        StackFrame::Typed {
+            activity,
+            since: SystemTime::now(),
+        }
+    }
+
+    fn name(&self) -> String {
+        match self {
+            StackFrame::Rendered(frame) => frame.activity.clone(),
+            StackFrame::Typed { activity, .. } => describe(&**activity),
+        }
+    }
+
+    fn since(&self) -> SystemTime {
+        match self {
+            StackFrame::Rendered(frame) => frame.since,
+            StackFrame::Typed { since, .. } => *since,
+        }
+    }
+
+    fn render(self) -> ActivityFrame {
+        match self {
+            StackFrame::Rendered(frame) => frame,
+            StackFrame::Typed { activity, since } => {
+                let fields = catch_unwind(AssertUnwindSafe(|| activity.fields()));
+                ActivityFrame {
+                    activity: describe(&*activity),
+                    fields: fields.unwrap_or_default(),
+                    since,
+                }
+            }
+        }
+    }
+}
+
+/// Describe a typed activity, isolating panics in the user implementation.
+fn describe(activity: &dyn ActivityReport) -> String {
+    catch_unwind(AssertUnwindSafe(|| activity.describe()))
+        .unwrap_or_else(|error| format!("(activity report panicked: {})", panic_message(&*error)))
+}
+
+impl From<ActivityFrame> for StackFrame {
+    fn from(frame: ActivityFrame) -> StackFrame {
+        StackFrame::Rendered(frame)
+    }
+}
+
+/// Point in time view of a thread's activities.
+pub(crate) struct ActivitySnapshot {
+    /// Innermost reported activity, if any.
+    pub(crate) current: Option<ActivityFrame>,
+
+    /// Reported activities, from the outermost to the innermost.
+    pub(crate) frames: Vec<ActivityFrame>,
+
+    /// Number of scoped activities that ended out of order.
+    pub(crate) out_of_order: u64,
+}
+
+/// Activity tracking shared by a thread's scope and the registry.
+///
+/// Most activity changes lock the activity stack, which is cheap but not free.
+/// Activities described by an `ActivityName` are instead stored in a seqlock
+/// that overlays the innermost entry of the stack so they can be reported without
+/// locks or allocations.
+/// The overlay is moved into the stack when a new scoped activity starts.
+pub(crate) struct ActivityState {
+    fast: FastActivity,
+    history: ThreadHistory,
+    latencies: ThreadLatencies,
+    stack: Mutex<ActivityStack>,
+    // Index of the innermost stack entry, readable without locking the stack.
+    top: AtomicUsize,
+    #[cfg(feature = "with_trace")]
+    trace: ThreadTrace,
+    utilization: Utilization,
+}
+
+impl ActivityState {
+    pub(crate) fn new(config: &StatusConfig) -> ActivityState {
+        ActivityState {
+            fast: FastActivity::default(),
+            history: ThreadHistory::new(config.history_size),
+            latencies: ThreadLatencies::default(),
+            stack: Mutex::new(ActivityStack::new()),
+            top: AtomicUsize::new(0),
+            #[cfg(feature = "with_trace")]
+            trace: ThreadTrace::default(),
+            utilization: Utilization::new(config.track_activity_time),
+        }
+    }
+
+    /// End the scoped activity with the given id.
+    ///
+    /// The duration of the scope is recorded by activity name.
+    pub(crate) fn pop(&self, scope: u64) {
+        let mut stack = self.lock();
+        let (index, entry) = match stack.pop(scope) {
+            Some(popped) => popped,
+            None => return,
+        };
+        let mut ended = entry.frame;
+        if let Some((name, depth, since)) = self.fast.load() {
+            if depth == index {
+                ended = Some(ActivityFrame::overlay(name, since).into());
+                self.fast.clear();
+            } else if depth > index {
+                self.fast.move_to(depth - 1);
+            }
+        }
+        self.top.store(stack.entries.len() - 1, Ordering::Release);
+        self.account(&stack);
+        drop(stack);
+        // Render and record the ended activity once the stack is unlocked.
+        if let Some(frame) = ended {
+            let activity = frame.name();
+            self.latencies
+                .record(activity.clone(), entry.started.elapsed());
+            self.finished(activity, frame.since());
+        }
+    }
+
+    /// Most recent activities completed by the thread, if history is enabled.
+    pub(crate) fn history(&self) -> Vec<ActivityRecord> {
+        self.history.records()
+    }
+
+    /// Summarise the duration of completed scoped activities.
+    pub(crate) fn latencies(&self) -> BTreeMap<String, LatencySummary> {
+        self.latencies.summaries()
+    }
+
+    /// Start a scoped activity and return the id of the scope.
+    pub(crate) fn push(&self, frame: StackFrame) -> u64 {
+        let mut stack = self.lock();
+        // The overlay can only track one activity so store the current one in the stack.
+        if let Some((name, depth, since)) = self.fast.load() {
+            if let Some(entry) = stack.entries.get_mut(depth) {
+                entry.frame = Some(ActivityFrame::overlay(name, since).into());
+            }
+            self.fast.clear();
+        }
+        let scope = stack.push(frame);
+        self.top.store(stack.entries.len() - 1, Ordering::Release);
+        self.account(&stack);
+        scope
+    }
+
+    /// Replace (or clear) the innermost activity.
+    pub(crate) fn set(&self, frame: Option<StackFrame>) {
+        let mut stack = self.lock();
+        let ended = self.end_current(&stack);
+        stack.set(frame);
+        let top = stack.entries.len() - 1;
+        if let Some((_, depth, _)) = self.fast.load() {
+            if depth == top {
+                self.fast.clear();
+            }
+        }
+        self.account(&stack);
+        drop(stack);
+        self.replaced(ended);
+    }
+
+    /// Replace the innermost activity without locking or allocating.
+    ///
+    /// The utilization tracker is only locked if the thread was idle or
+    /// activity time is tracked.
+    /// The stack is only locked if activity history is enabled or a trace is being recorded.
+    pub(crate) fn set_static(&self, name: ActivityName) {
+        if self.records_transitions() {
+            let stack = self.lock();
+            let ended = self.end_current(&stack);
+            let top = stack.entries.len() - 1;
+            self.fast.store(name, top, SystemTime::now());
+            drop(stack);
+            self.replaced(ended);
+        } else {
+            let top = self.top.load(Ordering::Acquire);
+            self.fast.store(name, top, SystemTime::now());
+        }
+        self.utilization
+            .transition(true, || Some(name.as_str().to_string()));
+    }
+
+    /// Compute how the thread spent its time.
+    pub(crate) fn utilization(&self) -> ThreadUtilization {
+        self.utilization.snapshot()
+    }
+
+    /// Collect a point in time view of the activities.
+    ///
+    /// Typed activities are rendered after the stack is unlocked.
+    pub(crate) fn snapshot(&self) -> ActivitySnapshot {
+        let stack = self.lock();
+        let fast = self.fast.load();
+        let mut frames: Vec<Option<StackFrame>> = stack
+            .entries
+            .iter()
+            .map(|entry| entry.frame.clone())
+            .collect();
+        let out_of_order = stack.out_of_order;
+        drop(stack);
+        if let Some((name, depth, since)) = fast {
+            if let Some(frame) = frames.get_mut(depth) {
+                *frame = Some(ActivityFrame::overlay(name, since).into());
+            }
+        }
+        let frames: Vec<Option<ActivityFrame>> = frames
+            .into_iter()
+            .map(|frame| frame.map(StackFrame::render))
+            .collect();
+        let current = frames.last().cloned().flatten();
+        ActivitySnapshot {
+            current,
+            frames: frames.into_iter().flatten().collect(),
+            out_of_order,
+        }
+    }
+
+    /// Describe the innermost reported activity, if any.
+    ///
+    /// Only the innermost activity is rendered, after the stack is unlocked.
+    #[cfg(feature = "with_log")]
+    pub(crate) fn innermost(&self) -> Option<String> {
+        let stack = self.lock();
+        let fast = self.fast.load();
+        let frame = stack
+            .entries
+            .iter()
+            .enumerate()
+            .rev()
+            .find_map(|(depth, entry)| match fast {
+                Some((name, fast_depth, since)) if fast_depth == depth => {
+                    Some(ActivityFrame::overlay(name, since).into())
+                }
+                _ => entry.frame.clone(),
+            });
+        drop(stack);
+        frame.map(|frame| frame.name())
+    }
+
+    /// Collect the activity frames without blocking or running user code.
+    ///
+    /// Typed activities are not rendered and are reported as `TYPED_PLACEHOLDER`.
+    /// Returns `None` if the stack is locked, for example while panicking with the lock held.
+    #[cfg(feature = "with_crash_report")]
+    pub(crate) fn try_untyped_frames(&self) -> Option<Vec<ActivityFrame>> {
+        let stack = match self.stack.try_lock() {
+            Ok(stack) => stack,
+            Err(TryLockError::Poisoned(poisoned)) => poisoned.into_inner(),
+            Err(TryLockError::WouldBlock) => return None,
+        };
+        let fast = self.fast.load();
+        let frames = stack
+            .entries
+            .iter()
+            .enumerate()
+            .filter_map(|(depth, entry)| match fast {
+                Some((name, fast_depth, since)) if fast_depth == depth => {
+                    Some(ActivityFrame::overlay(name, since))
+                }
+                _ => match entry.frame.as_ref()? {
+                    StackFrame::Rendered(frame) => Some(frame.clone()),
+                    StackFrame::Typed { since, .. } => Some(ActivityFrame {
+                        activity: TYPED_PLACEHOLDER.to_string(),
+                        fields: ActivityFields::new(),
+                        since: *since,
+                    }),
+                },
+            })
+            .collect();
+        Some(frames)
+    }
+
+    /// Account the time spent in the previous activity after the innermost activity changed.
+    fn account(&self, stack: &ActivityStack) {
+        let top = stack.entries.len() - 1;
+        let fast = self.fast.load().filter(|(_, depth, _)| *depth == top);
+        let frame = stack.entries[top].frame.as_ref();
+        let busy = fast.is_some() || frame.is_some();
+        self.utilization.transition(busy, || match fast {
+            Some((name, _, _)) => Some(name.as_str().to_string()),
+            None => frame.map(StackFrame::name),
+        });
+    }
+
+    /// Return the innermost activity about to end, if activity transitions are recorded.
+    ///
+    /// The activity is recorded with `replaced` once the stack is unlocked.
+    fn end_current(&self, stack: &ActivityStack) -> Option<StackFrame> {
+        if !self.records_transitions() {
+            return None;
+        }
+        let top = stack.entries.len() - 1;
+        match self.fast.load() {
+            Some((name, depth, since)) if depth == top => {
+                Some(ActivityFrame::overlay(name, since).into())
+            }
+            _ => stack.entries[top].frame.clone(),
+        }
+    }
+
+    /// Record an activity that was replaced, as returned by `end_current`.
+    fn replaced(&self, ended: Option<StackFrame>) {
+        if let Some(frame) = ended {
+            self.finished(frame.name(), frame.since());
+        }
+    }
+
+    /// Record a completed activity in the history and the trace being recorded, if any.
+    fn finished(&self, activity: String, since: SystemTime) {
+        #[cfg(feature = "with_trace")]
+        self.trace.record(&activity, since);
+        self.history.record(activity, since);
+    }
+
+    /// Push activities buffered for the trace in progress to the recorder.
+    #[cfg(feature = "with_trace")]
+    pub(crate) fn flush_trace(&self) {
+        self.trace.flush();
+    }
+
+    fn lock(&self) -> ::std::sync::MutexGuard<'_, ActivityStack> {
+        self.stack
+            .lock()
+            .expect("ActivityState::stack lock poisoned")
+    }
+
+    /// Check if replaced activities need to be recorded.
+    fn records_transitions(&self) -> bool {
+        #[cfg(feature = "with_trace")]
+        if trace::is_recording() {
+            return true;
+        }
+        self.history.is_enabled()
+    }
+}
+
+impl Default for ActivityState {
+    fn default() -> ActivityState {
+        ActivityState::new(&StatusConfig::default())
+    }
+}
+
+/// Seqlock storing the activity reported with an `ActivityName`.
+///
+/// The sequence number is odd while a write is in progress.
+/// Readers retry until they observe the same even sequence number before and after
+/// reading all fields, which guarantees the fields were written together.
+#[derive(Default)]
+struct FastActivity {
+    depth: AtomicUsize,
+    len: AtomicUsize,
+    // Pointer to the `&'static str` activity name, or 0 if no activity is set.
+    ptr: AtomicUsize,
+    seq: AtomicU64,
+    // Activity start time, as nanoseconds since the UNIX epoch.
+    since: AtomicU64,
+}
+
+impl FastActivity {
+    fn clear(&self) {
+        let seq = self.write_lock();
+        self.ptr.store(0, Ordering::Relaxed);
+        self.write_unlock(seq);
+    }
+
+    fn load(&self) -> Option<(ActivityName, usize, SystemTime)> {
+        loop {
+            let seq = self.seq.load(Ordering::Acquire);
+            if seq & 1 == 1 {
+                ::std::hint::spin_loop();
+                continue;
+            }
+            let depth = self.depth.load(Ordering::Relaxed);
+            let len = self.len.load(Ordering::Relaxed);
+            let ptr = self.ptr.load(Ordering::Relaxed);
+            let since = self.since.load(Ordering::Relaxed);
+            fence(Ordering::Acquire);
+            if self.seq.load(Ordering::Relaxed) != seq {
+                continue;
+            }
+            if ptr == 0 {
+                return None;
+            }
+            // SAFETY: `ptr` and `len` are always written together from a `&'static str`
+            // and the sequence check above ensures they were read from the same write.
+            let name: &'static str = unsafe {
+                let bytes = ::std::slice::from_raw_parts(ptr as *const u8, len);
+                ::std::str::from_utf8_unchecked(bytes)
+            };
+            let since = UNIX_EPOCH + Duration::from_nanos(since);
+            return Some((ActivityName(name), depth, since));
+        }
+    }
+
+    fn move_to(&self, depth: usize) {
+        let seq = self.write_lock();
+        self.depth.store(depth, Ordering::Relaxed);
+        self.write_unlock(seq);
+    }
+
+    fn store(&self, name: ActivityName, depth: usize, since: SystemTime) {
+        let since = since
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos() as u64;
+        let seq = self.write_lock();
+        self.depth.store(depth, Ordering::Relaxed);
+        self.len.store(name.0.len(), Ordering::Relaxed);
+        self.ptr.store(name.0.as_ptr() as usize, Ordering::Relaxed);
+        self.since.store(since, Ordering::Relaxed);
+        self.write_unlock(seq);
+    }
+
+    /// Make the sequence number odd, waiting for other writers if needed.
+    ///
+    /// Scopes can be cloned and sent to other threads so writers are not guaranteed to be unique.
+    fn write_lock(&self) -> u64 {
+        loop {
+            let seq = self.seq.load(Ordering::Relaxed);
+            if seq & 1 == 0
+                && self
+                    .seq
+                    .compare_exchange_weak(seq, seq + 1, Ordering::Acquire, Ordering::Relaxed)
+                    .is_ok()
+            {
+                fence(Ordering::Release);
+                return seq + 1;
+            }
+            ::std::hint::spin_loop();
+        }
+    }
+
+    fn write_unlock(&self, seq: u64) {
+        self.seq.store(seq + 1, Ordering::Release);
+    }
+}
+
+/// Internal tracking of nested activities reported by a thread.
+///
+/// The stack always has a base entry, changed by `ThreadScope::activity` and `ThreadScope::idle`
+/// when no scoped activity is in progress.
+/// Each scoped activity pushes a new entry that is removed when the scope ends.
+/// Entries are identified by a scope id so guards dropped out of order remove
+/// the correct entry and are counted instead of corrupting the stack.
+struct ActivityStack {
+    entries: Vec<ActivityEntry>,
+    next_scope: u64,
+    out_of_order: u64,
+}
+
+impl ActivityStack {
+    fn new() -> ActivityStack {
+        let base = ActivityEntry {
+            frame: None,
+            scope: 0,
+            started: Instant::now(),
+        };
+        ActivityStack {
+            entries: vec![base],
+            next_scope: 1,
+            out_of_order: 0,
+        }
+    }
+
+    /// End the scoped activity with the given id and return the removed entry and its index.
+    fn pop(&mut self, scope: u64) -> Option<(usize, ActivityEntry)> {
+        let index = self.entries.iter().rposition(|entry| entry.scope == scope);
+        let index = match index {
+            // The base entry is never removed.
+            Some(index) if index > 0 => index,
+            _ => return None,
+        };
+        if index != self.entries.len() - 1 {
+            self.out_of_order += 1;
+        }
+        let entry = self.entries.remove(index);
+        Some((index, entry))
+    }
+
+    /// Start a scoped activity and return the id of the scope.
+    fn push(&mut self, frame: StackFrame) -> u64 {
+        let scope = self.next_scope;
+        self.next_scope += 1;
+        self.entries.push(ActivityEntry {
+            frame: Some(frame),
+            scope,
+            started: Instant::now(),
+        });
+        scope
+    }
+
+    /// Replace (or clear) the innermost activity.
+    fn set(&mut self, frame: Option<StackFrame>) {
+        self.top_mut().frame = frame;
+    }
+
+    fn top_mut(&mut self) -> &mut ActivityEntry {
+        self.entries
+            .last_mut()
+            .expect("ActivityStack has no base entry")
+    }
+}
+
+struct ActivityEntry {
+    frame: Option<StackFrame>,
+    scope: u64,
+    started: Instant,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ActivityFields;
+    use super::ActivityFrame;
+    use super::ActivityName;
+    use super::ActivityReport;
+    use super::ActivityState;
+    use super::ActivityValue;
+    use super::StackFrame;
+    use super::StatusConfig;
+
+    fn frame(activity: &str) -> StackFrame {
+        ActivityFrame::new(activity.into(), ActivityFields::new()).into()
+    }
+
+    fn names(state: &ActivityState) -> Vec<String> {
+        state
+            .snapshot()
+            .frames
+            .into_iter()
+            .map(|frame| frame.activity)
+            .collect()
+    }
+
+    #[test]
+    fn nested_scopes() {
+        let stack = ActivityState::new(&StatusConfig::default());
+        stack.set(Some(frame("base")));
+        let outer = stack.push(frame("outer"));
+        let inner = stack.push(frame("inner"));
+        assert_eq!(vec!["base", "outer", "inner"], names(&stack));
+        assert_eq!("inner", stack.snapshot().current.unwrap().activity);
+        stack.pop(inner);
+        stack.pop(outer);
+        assert_eq!(vec!["base"], names(&stack));
+        assert_eq!(0, stack.snapshot().out_of_order);
+    }
+
+    #[test]
+    fn out_of_order_pop() {
+        let stack = ActivityState::new(&StatusConfig::default());
+        let outer = stack.push(frame("outer"));
+        let inner = stack.push(frame("inner"));
+        stack.pop(outer);
+        assert_eq!(vec!["inner"], names(&stack));
+        assert_eq!(1, stack.snapshot().out_of_order);
+        stack.pop(inner);
+        assert!(names(&stack).is_empty());
+        assert_eq!(1, stack.snapshot().out_of_order);
+    }
+
+    #[test]
+    fn set_replaces_innermost() {
+        let stack = ActivityState::new(&StatusConfig::default());
+        stack.set(Some(frame("base")));
+        let scope = stack.push(frame("outer"));
+        stack.set(Some(frame("replaced")));
+        assert_eq!(vec!["base", "replaced"], names(&stack));
+        stack.set(None);
+        assert_eq!(vec!["base"], names(&stack));
+        assert!(stack.snapshot().current.is_none());
+        stack.pop(scope);
+        assert_eq!("base", stack.snapshot().current.unwrap().activity);
+    }
+
+    #[test]
+    fn value_serialization() {
+        let fields = super::collect_fields(vec![
+            ("bool", ActivityValue::from(true)),
+            ("float", ActivityValue::from(1.5)),
+            ("int", ActivityValue::from(4)),
+            ("str", ActivityValue::from("text")),
+        ]);
+        let json = serde_json::to_string(&fields).expect("fields to serialize");
+        assert_eq!(r#"{"bool":true,"float":1.5,"int":4,"str":"text"}"#, json);
+        let back: ActivityFields = serde_json::from_str(&json).expect("fields to deserialize");
+        assert_eq!(fields, back);
+    }
+
+    #[test]
+    fn static_activity_overlay() {
+        let state = ActivityState::new(&StatusConfig::default());
+        state.set_static("base".into());
+        let outer = state.push(frame("outer"));
+        state.set_static(ActivityName::intern("replaced"));
+        let inner = state.push(frame("inner"));
+        let snapshot = state.snapshot();
+        let names: Vec<String> = snapshot
+            .frames
+            .into_iter()
+            .map(|frame| frame.activity)
+            .collect();
+        assert_eq!(vec!["base", "replaced", "inner"], names);
+        assert_eq!("inner", snapshot.current.unwrap().activity);
+
+        // Ending scopes restores overlaid activities.
+        state.pop(inner);
+        assert_eq!("replaced", state.snapshot().current.unwrap().activity);
+        state.pop(outer);
+        assert_eq!("base", state.snapshot().current.unwrap().activity);
+
+        // Slow path updates replace the overlay.
+        state.set(None);
+        assert!(state.snapshot().current.is_none());
+    }
+
+    #[test]
+    fn static_activity_out_of_order() {
+        let state = ActivityState::new(&StatusConfig::default());
+        let outer = state.push(frame("outer"));
+        let inner = state.push(frame("inner"));
+        state.set_static("replaced".into());
+        state.pop(outer);
+        assert_eq!("replaced", state.snapshot().current.unwrap().activity);
+        state.pop(inner);
+        assert!(state.snapshot().current.is_none());
+        assert_eq!(1, state.snapshot().out_of_order);
+    }
+
+    #[test]
+    fn intern_names_once() {
+        let first = ActivityName::intern("intern_names_once");
+        let second = ActivityName::intern(&String::from("intern_names_once"));
+        assert_eq!(first.as_str().as_ptr(), second.as_str().as_ptr());
+    }
+
+    #[test]
+    fn static_activity_concurrent_reads() {
+        let state = ::std::sync::Arc::new(ActivityState::new(&StatusConfig::default()));
+        let writer = ::std::sync::Arc::clone(&state);
+        let writer = ::std::thread::spawn(move || {
+            for _ in 0..10_000 {
+                writer.set_static("short".into());
+                writer.set_static("a much longer activity name".into());
+            }
+        });
+        while !writer.is_finished() {
+            if let Some(current) = state.snapshot().current {
+                assert!(
+                    current.activity == "short"
+                        || current.activity == "a much longer activity name"
+                );
+            }
+        }
+        writer.join().expect("the writer to stop");
+    }
+
+    struct Polling {
+        partition: i64,
+    }
+
+    impl ActivityReport for Polling {
+        fn describe(&self) -> String {
+            format!("polling partition {}", self.partition)
+        }
+
+        fn fields(&self) -> ActivityFields {
+            super::collect_fields(vec![("partition", self.partition)])
+        }
+    }
+
+    #[test]
+    fn typed_activity_rendered_on_snapshot() {
+        let state = ActivityState::new(&StatusConfig::default());
+        let activity = ::std::sync::Arc::new(Polling { partition: 3 });
+        state.set(Some(StackFrame::typed(activity)));
+        let current = state.snapshot().current.expect("activity to be reported");
+        assert_eq!("polling partition 3", current.activity);
+        assert_eq!(
+            Some(&ActivityValue::Int(3)),
+            current.fields.get("partition")
+        );
+    }
+
+    #[test]
+    fn utilization_follows_activities() {
+        let config = StatusConfig {
+            track_activity_time: true,
+            ..StatusConfig::default()
+        };
+        let state = ActivityState::new(&config);
+        let scope = state.push(frame("outer"));
+        ::std::thread::sleep(::std::time::Duration::from_millis(10));
+        state.set_static("fast".into());
+        ::std::thread::sleep(::std::time::Duration::from_millis(10));
+        state.pop(scope);
+        let utilization = state.utilization();
+        assert!(utilization.activities["outer"] >= ::std::time::Duration::from_millis(10));
+        assert!(utilization.activities["fast"] >= ::std::time::Duration::from_millis(10));
+        assert!(utilization.busy >= ::std::time::Duration::from_millis(20));
+    }
+
+    #[test]
+    fn scoped_latencies() {
+        let state = ActivityState::new(&StatusConfig::default());
+        for _ in 0..3 {
+            let scope = state.push(frame("stage"));
+            state.pop(scope);
+        }
+        let scope = state.push(frame("replaced"));
+        state.set_static("fast stage".into());
+        state.pop(scope);
+        let latencies = state.latencies();
+        assert_eq!(3, latencies["stage"].count);
+        assert_eq!(1, latencies["fast stage"].count);
+        assert!(!latencies.contains_key("replaced"));
+    }
+
+    #[test]
+    fn history_of_transitions() {
+        let config = StatusConfig {
+            history_size: 10,
+            ..StatusConfig::default()
+        };
+        let state = ActivityState::new(&config);
+        state.set(Some(frame("first")));
+        state.set_static("second".into());
+        let scope = state.push(frame("scoped"));
+        state.pop(scope);
+        state.set(None);
+        let activities: Vec<String> = state
+            .history()
+            .into_iter()
+            .map(|record| record.activity)
+            .collect();
+        assert_eq!(vec!["first", "scoped", "second"], activities);
+    }
+}
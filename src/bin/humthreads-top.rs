@@ -0,0 +1,433 @@
+//! Show the threads of a process serving humthreads introspection over a socket or HTTP.
+use std::collections::HashMap;
+use std::io::Read;
+use std::io::Write;
+use std::net::TcpStream;
+use std::process::exit;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+
+use humthreads::align_columns;
+use humthreads::format_age;
+use humthreads::ThreadQuery;
+use humthreads::ThreadStatus;
+
+const USAGE: &str = "\
+Usage: humthreads-top (--socket PATH | --http ADDRESS) [OPTIONS]
+
+Show a refreshing table of the threads registered in a process.
+
+Options:
+  --socket PATH       Connect to the introspection socket at PATH
+  --http ADDRESS      Connect to the HTTP introspection server at ADDRESS (host:port)
+  --sort COLUMN       Sort threads by name (default), state, age, cpu or id
+  --name TEXT         Only show threads with a name that contains TEXT
+  --group NAME        Only show threads in the named group
+  --interval SECONDS  Time between refreshes (default 2)
+  --json              Print a single snapshot as JSON and exit
+  -h, --help          Show this message and exit
+";
+
+/// Where to fetch snapshots from.
+enum Source {
+    Http(String),
+    Socket(String),
+}
+
+/// Columns threads can be sorted by.
+#[derive(Clone, Copy)]
+enum SortBy {
+    Age,
+    Cpu,
+    Id,
+    Name,
+    State,
+}
+
+struct Options {
+    interval: Duration,
+    json: bool,
+    query: ThreadQuery,
+    sort: SortBy,
+    source: Source,
+}
+
+fn main() {
+    let options = match parse_args(::std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(error) => {
+            eprintln!("{}\n\n{}", error, USAGE);
+            exit(2);
+        }
+    };
+    if let Err(error) = run(options) {
+        eprintln!("humthreads-top: {}", error);
+        exit(1);
+    }
+}
+
+fn parse_args<I: Iterator<Item = String>>(mut args: I) -> Result<Options, String> {
+    let mut interval = Duration::from_secs(2);
+    let mut json = false;
+    let mut query = ThreadQuery::new();
+    let mut sort = SortBy::Name;
+    let mut source = None;
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("missing value for {}", arg))
+        };
+        match arg.as_str() {
+            "-h" | "--help" => {
+                print!("{}", USAGE);
+                exit(0);
+            }
+            "--group" => query = query.group(value()?),
+            "--http" => source = Some(Source::Http(value()?)),
+            "--interval" => {
+                let secs: f64 = value()?
+                    .parse()
+                    .map_err(|_| "--interval must be a number of seconds".to_string())?;
+                if !secs.is_finite() || secs <= 0.0 {
+                    return Err("--interval must be positive".into());
+                }
+                interval = Duration::from_secs_f64(secs);
+            }
+            "--json" => json = true,
+            "--name" => query = query.name_contains(value()?),
+            "--socket" => source = Some(Source::Socket(value()?)),
+            "--sort" => {
+                sort = match value()?.as_str() {
+                    "age" => SortBy::Age,
+                    "cpu" => SortBy::Cpu,
+                    "id" => SortBy::Id,
+                    "name" => SortBy::Name,
+                    "state" => SortBy::State,
+                    other => return Err(format!("unknown sort column {}", other)),
+                }
+            }
+            other => return Err(format!("unknown argument {}", other)),
+        }
+    }
+    let source = source.ok_or_else(|| "one of --socket or --http is required".to_string())?;
+    Ok(Options {
+        interval,
+        json,
+        query,
+        sort,
+        source,
+    })
+}
+
+fn run(options: Options) -> Result<(), String> {
+    if options.json {
+        let threads = select(fetch(&options.source)?, &options, &HashMap::new());
+        let json = serde_json::to_string_pretty(&threads).map_err(|error| error.to_string())?;
+        println!("{}", json);
+        return Ok(());
+    }
+
+    // CPU usage is computed from the CPU time consumed between two snapshots.
+    let mut previous: HashMap<u64, Duration> = HashMap::new();
+    let mut previous_at = Instant::now();
+    loop {
+        let threads = fetch(&options.source)?;
+        let now = Instant::now();
+        let elapsed = now.duration_since(previous_at).as_secs_f64();
+        let cpu: HashMap<u64, f64> = threads
+            .iter()
+            .filter_map(|thread| {
+                let used = thread.cpu_time?.checked_sub(*previous.get(&thread.id)?)?;
+                Some((thread.id, used.as_secs_f64() * 100.0 / elapsed))
+            })
+            .collect();
+        previous = threads
+            .iter()
+            .filter_map(|thread| thread.cpu_time.map(|cpu| (thread.id, cpu)))
+            .collect();
+        previous_at = now;
+
+        let threads = select(threads, &options, &cpu);
+        let mut out = String::from("\x1b[2J\x1b[H");
+        out.push_str(&render(&threads, &cpu));
+        let mut stdout = ::std::io::stdout();
+        stdout
+            .write_all(out.as_bytes())
+            .and_then(|_| stdout.flush())
+            .map_err(|error| error.to_string())?;
+        ::std::thread::sleep(options.interval);
+    }
+}
+
+/// Filter and sort threads according to the options.
+fn select(
+    threads: Vec<ThreadStatus>,
+    options: &Options,
+    cpu: &HashMap<u64, f64>,
+) -> Vec<ThreadStatus> {
+    let mut threads: Vec<ThreadStatus> = threads
+        .into_iter()
+        .filter(|thread| options.query.matches(thread))
+        .collect();
+    threads.sort_by(|a, b| {
+        let by_name = a.name.cmp(&b.name).then(a.id.cmp(&b.id));
+        match options.sort {
+            SortBy::Age => activity_since(a).cmp(&activity_since(b)).then(by_name),
+            SortBy::Cpu => {
+                let a_cpu = cpu.get(&a.id).copied().unwrap_or(0.0);
+                let b_cpu = cpu.get(&b.id).copied().unwrap_or(0.0);
+                b_cpu.total_cmp(&a_cpu).then(by_name)
+            }
+            SortBy::Id => a.id.cmp(&b.id),
+            SortBy::Name => by_name,
+            SortBy::State => a.state().cmp(b.state()).then(by_name),
+        }
+    });
+    threads
+}
+
+/// Render threads as a column aligned table.
+fn render(threads: &[ThreadStatus], cpu: &HashMap<u64, f64>) -> String {
+    let now = SystemTime::now();
+    let header = ["NAME", "STATE", "ACTIVITY", "AGE", "CPU%", "LABELS"].map(String::from);
+    let mut rows = vec![header.to_vec()];
+    for thread in threads {
+        let age = activity_since(thread)
+            .and_then(|since| now.duration_since(since).ok())
+            .map(format_age)
+            .unwrap_or_else(|| "-".into());
+        let cpu = cpu
+            .get(&thread.id)
+            .map(|cpu| format!("{:.1}", cpu))
+            .unwrap_or_else(|| "-".into());
+        let labels: Vec<String> = thread
+            .labels
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect();
+        rows.push(vec![
+            thread.name.clone(),
+            thread.state().to_string(),
+            thread.activity.clone().unwrap_or_else(|| "-".into()),
+            age,
+            cpu,
+            labels.join(","),
+        ]);
+    }
+    align_columns(&rows)
+}
+
+fn activity_since(thread: &ThreadStatus) -> Option<SystemTime> {
+    thread.activity_stack.last().map(|frame| frame.since)
+}
+
+/// Fetch a snapshot from the introspected process.
+///
+/// A new connection is used for each snapshot so the server is not tied up between refreshes.
+fn fetch(source: &Source) -> Result<Vec<ThreadStatus>, String> {
+    match source {
+        Source::Http(address) => fetch_http(address),
+        #[cfg(unix)]
+        Source::Socket(path) => humthreads::SocketClient::connect(path)
+            .and_then(|mut client| client.snapshot_with_cpu_time())
+            .map_err(|error| error.to_string()),
+        #[cfg(not(unix))]
+        Source::Socket(_) => Err("--socket is only supported on unix".into()),
+    }
+}
+
+/// Fetch a snapshot from the `/threads` endpoint of an HTTP introspection server.
+fn fetch_http(address: &str) -> Result<Vec<ThreadStatus>, String> {
+    let mut stream = TcpStream::connect(address).map_err(|error| error.to_string())?;
+    let request = format!(
+        "GET /threads?cpu_time=true HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+        address
+    );
+    let mut response = String::new();
+    stream
+        .write_all(request.as_bytes())
+        .and_then(|_| stream.read_to_string(&mut response))
+        .map_err(|error| error.to_string())?;
+    let (head, body) = response
+        .split_once("\r\n\r\n")
+        .ok_or_else(|| "invalid HTTP response".to_string())?;
+    let status = head.lines().next().unwrap_or("");
+    if !status.starts_with("HTTP/1.1 200") {
+        return Err(format!("unexpected HTTP response: {}", status));
+    }
+    serde_json::from_str(body).map_err(|error| error.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::time::Duration;
+    use std::time::SystemTime;
+
+    use humthreads::ActivityFrame;
+    use humthreads::ThreadQuery;
+    use humthreads::ThreadStatus;
+
+    use super::parse_args;
+    use super::render;
+    use super::select;
+    use super::Options;
+    use super::SortBy;
+    use super::Source;
+
+    fn args(args: &[&str]) -> Result<Options, String> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    fn options(query: ThreadQuery, sort: SortBy) -> Options {
+        Options {
+            interval: Duration::from_secs(2),
+            json: false,
+            query,
+            sort,
+            source: Source::Socket("test.sock".into()),
+        }
+    }
+
+    /// Snapshot of a thread with the given name, id and activity started `age` ago.
+    fn thread(name: &str, id: u64, age: Option<Duration>) -> ThreadStatus {
+        let frame = age.map(|age| ActivityFrame {
+            activity: format!("{} activity", name),
+            fields: Default::default(),
+            since: SystemTime::now() - age,
+        });
+        ThreadStatus {
+            activity: frame.as_ref().map(|frame| frame.activity.clone()),
+            activity_fields: Default::default(),
+            activity_out_of_order: 0,
+            activity_stack: frame.into_iter().collect(),
+            counters: Default::default(),
+            cpu_time: None,
+            gauges: Default::default(),
+            group: None,
+            history: Vec::new(),
+            id,
+            inspect_errors: Vec::new(),
+            inspected: Default::default(),
+            labels: Default::default(),
+            latencies: Default::default(),
+            name: name.to_string(),
+            os_id: None,
+            parent: None,
+            progress: None,
+            short_name: name.to_string(),
+            shutdown_requested: false,
+            utilization: Default::default(),
+        }
+    }
+
+    fn names(threads: &[ThreadStatus]) -> Vec<&str> {
+        threads.iter().map(|thread| thread.name.as_str()).collect()
+    }
+
+    #[test]
+    fn parse_args_defaults() {
+        let options = args(&["--http", "127.0.0.1:8080"]).expect("valid arguments");
+        assert_eq!(Duration::from_secs(2), options.interval);
+        assert!(!options.json);
+        assert!(matches!(options.sort, SortBy::Name));
+        assert!(matches!(options.source, Source::Http(ref address) if address == "127.0.0.1:8080"));
+    }
+
+    #[test]
+    fn parse_args_options() {
+        let options = args(&[
+            "--socket",
+            "/tmp/app.sock",
+            "--sort",
+            "cpu",
+            "--interval",
+            "0.5",
+            "--json",
+        ])
+        .expect("valid arguments");
+        assert_eq!(Duration::from_millis(500), options.interval);
+        assert!(options.json);
+        assert!(matches!(options.sort, SortBy::Cpu));
+        assert!(matches!(options.source, Source::Socket(ref path) if path == "/tmp/app.sock"));
+    }
+
+    #[test]
+    fn parse_args_errors() {
+        let error = |list: &[&str]| args(list).err().expect("invalid arguments");
+        assert_eq!("one of --socket or --http is required", error(&[]));
+        assert_eq!("missing value for --socket", error(&["--socket"]));
+        assert_eq!(
+            "unknown sort column size",
+            error(&["--socket", "a", "--sort", "size"])
+        );
+        assert_eq!(
+            "--interval must be positive",
+            error(&["--socket", "a", "--interval", "0"])
+        );
+        assert_eq!("unknown argument --verbose", error(&["--verbose"]));
+    }
+
+    #[test]
+    fn select_filters_and_sorts() {
+        let threads = vec![
+            thread("top-worker-b", 2, Some(Duration::from_secs(5))),
+            thread("top-other", 3, None),
+            thread("top-worker-a", 1, Some(Duration::from_secs(60))),
+        ];
+        let mut cpu = HashMap::new();
+        cpu.insert(2, 50.0);
+        cpu.insert(1, 10.0);
+
+        let by_name = options(ThreadQuery::new().name_contains("worker"), SortBy::Name);
+        let selected = select(threads.clone(), &by_name, &cpu);
+        assert_eq!(vec!["top-worker-a", "top-worker-b"], names(&selected));
+
+        let by_cpu = options(ThreadQuery::new(), SortBy::Cpu);
+        let selected = select(threads.clone(), &by_cpu, &cpu);
+        assert_eq!(
+            vec!["top-worker-b", "top-worker-a", "top-other"],
+            names(&selected)
+        );
+
+        let by_state = options(ThreadQuery::new(), SortBy::State);
+        let selected = select(threads, &by_state, &cpu);
+        assert_eq!(
+            vec!["top-worker-a", "top-worker-b", "top-other"],
+            names(&selected)
+        );
+    }
+
+    #[test]
+    fn render_table() {
+        let threads = vec![
+            thread("top-render-busy", 1, Some(Duration::from_secs(65))),
+            thread("top-render-idle", 2, None),
+        ];
+        let mut cpu = HashMap::new();
+        cpu.insert(1, 12.34);
+        let table = render(&threads, &cpu);
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(3, lines.len());
+        let header: Vec<&str> = lines[0].split_whitespace().collect();
+        assert_eq!(
+            vec!["NAME", "STATE", "ACTIVITY", "AGE", "CPU%", "LABELS"],
+            header
+        );
+        let busy: Vec<&str> = lines[1].split_whitespace().collect();
+        assert_eq!(
+            vec![
+                "top-render-busy",
+                "busy",
+                "top-render-busy",
+                "activity",
+                "1m5s",
+                "12.3"
+            ],
+            busy
+        );
+        let idle: Vec<&str> = lines[2].split_whitespace().collect();
+        assert_eq!(vec!["top-render-idle", "idle", "-", "-", "-"], idle);
+    }
+}
@@ -4,12 +4,17 @@ use std::thread::Builder as StdBuilder;
 
 use failure::ResultExt;
 
+use super::current::set_current;
+use super::handles::RegisteredThreadGuard;
 use super::handles::ThreadGuard;
 use super::registry::current_thread_id;
+use super::registry::is_registered;
 use super::status::RegisteredStatus;
+use super::status::StatusConfig;
 use super::ErrorKind;
 use super::Result;
 use super::Thread;
+use super::ThreadGroup;
 use super::ThreadScope;
 
 /// Thread factory to configure the properties of a new thread.
@@ -23,7 +28,9 @@ use super::ThreadScope;
 ///
 /// [`std::thread`]: https://doc.rust-lang.org/std/thread/index.html
 pub struct Builder {
+    config: StatusConfig,
     full_name: String,
+    group: Option<ThreadGroup>,
     name: String,
     std: StdBuilder,
 }
@@ -33,8 +40,10 @@ impl Builder {
         let name = name.into();
         let std = StdBuilder::new().name(name.clone());
         Builder {
+            config: StatusConfig::default(),
             name: name.clone(),
             full_name: name,
+            group: None,
             std,
         }
     }
@@ -51,11 +60,73 @@ impl Builder {
         self
     }
 
+    /// Spawn the thread as a member of the given [`ThreadGroup`].
+    ///
+    /// [`ThreadGroup`]: struct.ThreadGroup.html
+    pub fn group(mut self, group: &ThreadGroup) -> Builder {
+        self.group = Some(group.clone());
+        self
+    }
+
+    /// Keep a history of the last `size` activities completed by the thread.
+    ///
+    /// Activities are recorded when they are replaced, cleared or when their scope ends.
+    /// History is disabled by default (`size` is 0) because enabling it makes
+    /// [`ThreadScope::static_activity`] lock the activity stack.
+    ///
+    /// See [`thread_history`] and [`ThreadQuery::with_history`] to access the history.
+    ///
+    /// [`ThreadQuery::with_history`]: struct.ThreadQuery.html#method.with_history
+    /// [`ThreadScope::static_activity`]: struct.ThreadScope.html#method.static_activity
+    /// [`thread_history`]: fn.thread_history.html
+    pub fn history(mut self, size: usize) -> Builder {
+        self.config.history_size = size;
+        self
+    }
+
+    /// Attach a static label to the thread.
+    ///
+    /// Labels describe the thread itself (the component it belongs to, a shard, ...)
+    /// and are reported in [`ThreadStatus::labels`] and by exporters.
+    /// Setting a label that is already set replaces its value.
+    ///
+    /// [`ThreadStatus::labels`]: struct.ThreadStatus.html#structfield.labels
+    pub fn label<K, V>(mut self, key: K, value: V) -> Builder
+    where
+        K: Into<String>,
+        V: Into<String>,
+    {
+        self.config.labels.insert(key.into(), value.into());
+        self
+    }
+
+    /// Track the time the thread spends in each activity.
+    ///
+    /// Busy and idle time is always tracked but time spent in each activity is only
+    /// tracked if enabled because it requires looking up and storing activity names
+    /// every time the activity changes.
+    /// At most 64 distinct activities are tracked, time in other activities is
+    /// reported under `(other)`.
+    /// Activity times are reported in [`ThreadUtilization::activities`].
+    ///
+    /// [`ThreadUtilization::activities`]: struct.ThreadUtilization.html#structfield.activities
+    pub fn track_activity_time(mut self, enabled: bool) -> Builder {
+        self.config.track_activity_time = enabled;
+        self
+    }
+
     /// Spawns a new thread by taking ownership of the Builder.
     ///
     /// On success a [`Thread`] handle is returned.
     ///
+    /// If the calling thread is itself registered it is recorded as the parent of the new thread.
+    ///
+    /// The [`ThreadScope`] passed to `f` is also available to code running in the new thread
+    /// through [`current`] and the other thread-local helpers.
+    ///
     /// [`Thread`]: struct.Thread.html
+    /// [`ThreadScope`]: struct.ThreadScope.html
+    /// [`current`]: fn.current.html
     pub fn spawn<F, T>(self, f: F) -> Result<Thread<T>>
     where
         F: FnOnce(ThreadScope) -> T,
@@ -63,28 +134,86 @@ impl Builder {
         T: Send + 'static,
     {
         let (join_check_send, join_check_receive) = ::crossbeam_channel::bounded(1);
+        let config = self.config;
         let full_name = self.full_name;
+        let handle_name = full_name.clone();
+        let group = self.group;
         let name = self.name;
         let shutdown = Arc::new(AtomicBool::new(false));
         let scope_shutdown = Arc::clone(&shutdown);
+        let membership = group
+            .as_ref()
+            .map(|group| group.join_group(Arc::clone(&shutdown)));
+        let parent = current_thread_id();
+        let parent = if is_registered(parent) {
+            Some(parent)
+        } else {
+            None
+        };
         let join = self
             .std
-            .spawn(|| {
+            .spawn(move || {
                 let id = current_thread_id();
-                let status = RegisteredStatus::new(full_name, name);
-                let activity = status.activity();
+                let status = RegisteredStatus::new(
+                    id,
+                    full_name,
+                    name,
+                    parent,
+                    group,
+                    config,
+                    scope_shutdown,
+                );
+                let scope = status.scope();
+                set_current(Some(&status));
                 // Keep a ThreadGuard alive as long as the thread is.
-                let _guard = ThreadGuard::new(id, join_check_send, status);
-                let scope = ThreadScope::new(activity, scope_shutdown);
+                let _guard = ThreadGuard::new(id, Some(join_check_send), status, membership);
                 f(scope)
             })
             .with_context(|_| ErrorKind::Spawn)?;
-        Ok(Thread::new(join, join_check_receive, shutdown))
+        Ok(Thread::new(join, join_check_receive, handle_name, shutdown))
+    }
+}
+
+/// Register the current thread even if it was not spawned by a [`Builder`].
+///
+/// This allows threads such as the main thread, or threads started by other libraries,
+/// to be inspected and report their activity with a [`ThreadScope`].
+/// The thread is deregistered when the returned [`RegisteredThreadGuard`] is dropped.
+///
+/// The OS thread name, if set, is reported as the short name of the thread.
+///
+/// [`Builder`]: struct.Builder.html
+/// [`RegisteredThreadGuard`]: struct.RegisteredThreadGuard.html
+/// [`ThreadScope`]: struct.ThreadScope.html
+pub fn register_current_thread<S: Into<String>>(
+    name: S,
+) -> Result<(RegisteredThreadGuard, ThreadScope)> {
+    let id = current_thread_id();
+    if is_registered(id) {
+        return Err(ErrorKind::AlreadyRegistered.into());
     }
+    let full_name = name.into();
+    let name = ::std::thread::current()
+        .name()
+        .map(String::from)
+        .unwrap_or_else(|| full_name.clone());
+    let shutdown = Arc::new(AtomicBool::new(false));
+    let config = StatusConfig::default();
+    let status = RegisteredStatus::new(id, full_name, name, None, None, config, shutdown);
+    let scope = status.scope();
+    set_current(Some(&status));
+    let guard = ThreadGuard::new(id, None, status, None);
+    Ok((RegisteredThreadGuard::new(guard), scope))
 }
 
 #[cfg(test)]
 mod tests {
+    use std::thread::Builder as StdBuilder;
+    use std::time::Duration;
+
+    use super::super::registered_threads;
+    use super::super::ThreadGroup;
+    use super::register_current_thread;
     use super::Builder;
 
     #[test]
@@ -95,4 +224,44 @@ mod tests {
             .join()
             .expect("failed to join thread");
     }
+
+    #[test]
+    fn register_current_thread_lifecycle() {
+        let thread = ::std::thread::spawn(|| {
+            let (guard, scope) =
+                register_current_thread("register current thread").expect("to register the thread");
+            scope.activity("registered by hand");
+            let registered = registered_threads();
+            let again = register_current_thread("register current thread again");
+            drop(guard);
+            (registered, again.is_err(), registered_threads())
+        });
+        let (registered, again, deregistered) = thread.join().expect("the thread to stop");
+
+        let status = registered
+            .iter()
+            .find(|t| t.name == "register current thread")
+            .expect("registered thread not found");
+        assert_eq!(Some("registered by hand".into()), status.activity);
+        assert!(again);
+        assert!(deregistered
+            .iter()
+            .all(|t| t.name != "register current thread"));
+    }
+
+    #[test]
+    fn failed_spawn_leaves_group() {
+        let group = ThreadGroup::new("failed_spawn_leaves_group");
+        let mut builder = Builder::new("failed_spawn_leaves_group").group(&group);
+        // Threads with an impossibly large stack fail to spawn.
+        builder.std = StdBuilder::new().stack_size(1 << 62);
+        assert!(builder.spawn(|_| {}).is_err());
+
+        let status = group.status();
+        assert_eq!(0, status.running);
+        assert_eq!(0, status.exited);
+        group
+            .join_timeout(Duration::from_millis(10))
+            .expect("the group to have no running threads");
+    }
 }
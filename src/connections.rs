@@ -0,0 +1,43 @@
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+/// Slot counting a connection being handled by an introspection server, released when dropped.
+pub(crate) struct ConnectionSlot(Arc<AtomicUsize>);
+
+impl ConnectionSlot {
+    /// Reserve a slot unless `limit` connections are already being handled.
+    pub(crate) fn acquire(connections: &Arc<AtomicUsize>, limit: usize) -> Option<ConnectionSlot> {
+        let slot = ConnectionSlot(Arc::clone(connections));
+        if connections.fetch_add(1, Ordering::AcqRel) >= limit {
+            return None;
+        }
+        Some(slot)
+    }
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::Arc;
+
+    use super::ConnectionSlot;
+
+    #[test]
+    fn slots_are_limited_and_released() {
+        let connections = Arc::new(AtomicUsize::new(0));
+        let first = ConnectionSlot::acquire(&connections, 2).expect("a free slot");
+        let second = ConnectionSlot::acquire(&connections, 2).expect("a free slot");
+        assert!(ConnectionSlot::acquire(&connections, 2).is_none());
+        drop(first);
+        assert!(ConnectionSlot::acquire(&connections, 2).is_some());
+        drop(second);
+        assert_eq!(0, connections.load(::std::sync::atomic::Ordering::Relaxed));
+    }
+}
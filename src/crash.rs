@@ -0,0 +1,267 @@
+use std::cell::RefCell;
+use std::fs::File;
+use std::panic::catch_unwind;
+use std::panic::AssertUnwindSafe;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use failure::ResultExt;
+use serde::Deserialize;
+use serde::Serialize;
+
+use super::current::try_untyped_activity_stack;
+use super::inspect::panic_message;
+use super::registry::uninspected_threads;
+use super::ActivityFrame;
+use super::ErrorKind;
+use super::Result;
+use super::ThreadStatus;
+
+/// Sequence number to tell apart reports written in the same second.
+static NEXT_REPORT: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    /// Most recent panic of the current thread, reported if it unwinds out of the thread.
+    static LAST_PANIC: RefCell<Option<PanicDetails>> = const { RefCell::new(None) };
+}
+
+/// Report written when a registered thread panics.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct CrashReport {
+    /// False if some threads, possibly including the panicking one, could not be included.
+    pub complete: bool,
+
+    /// Source location of the panic, if known.
+    pub location: Option<String>,
+
+    /// Panic message.
+    pub message: String,
+
+    /// Status of the panicking thread when it panicked.
+    pub thread: Option<ThreadStatus>,
+
+    /// Status of all other registered threads when the panic happened.
+    pub threads: Vec<ThreadStatus>,
+
+    /// Time of the panic.
+    pub time: SystemTime,
+}
+
+/// Install a panic hook writing a [`CrashReport`] to `directory` when a registered thread panics.
+///
+/// The report includes the full name and activity stack of the panicking thread as well
+/// as the status of all other registered threads.
+/// Reports are named `humthreads-crash-<pid>-<unix time>-<sequence>.json`.
+///
+/// Only panics that unwind out of a registered thread are reported: the hook itself only
+/// records the panic details and the report is written when the thread is deregistered.
+/// Panics caught with `catch_unwind`, including those in inspect callbacks, are not reported.
+/// Inspect callbacks are not invoked when taking the snapshot and `complete` is false
+/// if some threads could not be included in the report.
+/// Typed activities of the panicking thread are not rendered while panicking
+/// and are reported as `(typed activity)`.
+///
+/// The previously installed hook is still invoked, so the standard panic message is still printed.
+///
+/// [`CrashReport`]: struct.CrashReport.html
+pub fn install_crash_reports<P: Into<PathBuf>>(directory: P) -> Result<()> {
+    let directory = directory.into();
+    ::std::fs::create_dir_all(&directory).with_context(|_| ErrorKind::CrashReport)?;
+    let previous = ::std::panic::take_hook();
+    ::std::panic::set_hook(Box::new(move |info| {
+        // Do as little as possible while panicking: no locks and no user code.
+        let panic = PanicDetails {
+            activity_stack: try_untyped_activity_stack(),
+            directory: directory.clone(),
+            location: info.location().map(|location| location.to_string()),
+            message: panic_message(info.payload()).to_string(),
+            time: SystemTime::now(),
+        };
+        let _ = LAST_PANIC.try_with(|last| {
+            if let Ok(mut last) = last.try_borrow_mut() {
+                *last = Some(panic);
+            }
+        });
+        previous(info);
+    }));
+    Ok(())
+}
+
+/// Write a crash report for a registered thread unwinding because of a panic.
+///
+/// Called by the thread before it is deregistered, once the panic reached the thread boundary.
+pub(crate) fn thread_panicked(id: u64) {
+    let panic = LAST_PANIC
+        .try_with(|last| last.try_borrow_mut().ok().and_then(|mut last| last.take()))
+        .ok()
+        .flatten();
+    let panic = match panic {
+        Some(panic) => panic,
+        None => return,
+    };
+    // Nothing can be done if the report can't be created or written.
+    let _ = catch_unwind(AssertUnwindSafe(|| {
+        let (threads, complete) = uninspected_threads();
+        let (thread, threads): (Vec<_>, Vec<_>) =
+            threads.into_iter().partition(|thread| thread.id == id);
+        let mut thread = thread.into_iter().next();
+        // Activities were popped while unwinding so report those seen by the hook.
+        if let (Some(thread), Some(stack)) = (thread.as_mut(), panic.activity_stack) {
+            let current = stack.last();
+            thread.activity = current.map(|frame| frame.activity.clone());
+            thread.activity_fields = current
+                .map(|frame| frame.fields.clone())
+                .unwrap_or_default();
+            thread.activity_stack = stack;
+        }
+        let report = CrashReport {
+            complete: complete && thread.is_some(),
+            location: panic.location,
+            message: panic.message,
+            thread,
+            threads,
+            time: panic.time,
+        };
+        write_report(&panic.directory, &report)
+    }));
+}
+
+/// Panic details recorded by the hook until the panic reaches the thread boundary.
+struct PanicDetails {
+    /// Activities of the thread when it panicked, before they are unwound.
+    activity_stack: Option<Vec<ActivityFrame>>,
+    directory: PathBuf,
+    location: Option<String>,
+    message: String,
+    time: SystemTime,
+}
+
+/// Write the report to a new file in the given directory.
+fn write_report(directory: &Path, report: &CrashReport) -> ::std::io::Result<()> {
+    let timestamp = report
+        .time
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs())
+        .unwrap_or(0);
+    let name = format!(
+        "humthreads-crash-{}-{}-{}.json",
+        ::std::process::id(),
+        timestamp,
+        NEXT_REPORT.fetch_add(1, Ordering::Relaxed)
+    );
+    let file = File::create(directory.join(name))?;
+    serde_json::to_writer_pretty(file, report)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::panic::PanicHookInfo;
+    use std::path::Path;
+    use std::sync::Arc;
+
+    use super::super::Builder;
+    use super::install_crash_reports;
+    use super::CrashReport;
+
+    type Hook = Arc<dyn Fn(&PanicHookInfo) + Send + Sync>;
+
+    /// Install crash reports to a test directory and restore the previous panic hook afterwards.
+    fn with_crash_reports<F: FnOnce()>(name: &str, test: F) -> Vec<CrashReport> {
+        let directory = ::std::env::temp_dir().join(format!(
+            "humthreads-crash-{}-{}",
+            name,
+            ::std::process::id()
+        ));
+        let previous: Hook = Arc::from(::std::panic::take_hook());
+        let chained = Arc::clone(&previous);
+        ::std::panic::set_hook(Box::new(move |info| chained(info)));
+        install_crash_reports(&directory).expect("to install the panic hook");
+        test();
+        drop(::std::panic::take_hook());
+        ::std::panic::set_hook(Box::new(move |info| previous(info)));
+        let reports = read_reports(&directory);
+        let _ = ::std::fs::remove_dir_all(&directory);
+        reports
+    }
+
+    fn read_reports(directory: &Path) -> Vec<CrashReport> {
+        ::std::fs::read_dir(directory)
+            .expect("to list crash reports")
+            .filter_map(|entry| {
+                let file = File::open(entry.ok()?.path()).ok()?;
+                serde_json::from_reader(file).ok()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn caught_panics_are_not_reported() {
+        let reports = with_crash_reports("caught", || {
+            let thread = Builder::new("caught_panics_are_not_reported")
+                .spawn(|_| {
+                    let caught = ::std::panic::catch_unwind(|| {
+                        panic!("this panic is expected");
+                    });
+                    assert!(caught.is_err());
+                })
+                .expect("to spawn test thread");
+            thread.join().expect("the thread to stop");
+        });
+        assert!(reports.iter().all(|report| report
+            .thread
+            .as_ref()
+            .map(|thread| thread.name != "caught_panics_are_not_reported")
+            .unwrap_or(true)));
+    }
+
+    #[test]
+    fn report_on_panic() {
+        let reports = with_crash_reports("report", || {
+            let thread = Builder::new("report_on_panic")
+                .spawn(|scope| {
+                    let _request =
+                        scope.scoped_activity_with("handling request", vec![("request", 4)]);
+                    let _parse = scope.scoped_activity("parsing");
+                    panic!("this panic is expected");
+                })
+                .expect("to spawn test thread");
+            assert!(thread.join().is_err());
+        });
+
+        // Other tests may be panicking at the same time so look for this thread's report.
+        let report = reports
+            .into_iter()
+            .find(|report| {
+                report
+                    .thread
+                    .as_ref()
+                    .map(|thread| thread.name == "report_on_panic")
+                    .unwrap_or(false)
+            })
+            .expect("crash report not found");
+        let thread = report.thread.expect("panicking thread in the report");
+
+        assert!(report.complete);
+        assert_eq!("this panic is expected", report.message);
+        assert!(report
+            .location
+            .expect("panic location")
+            .starts_with("src/crash.rs:"));
+        let activities: Vec<&str> = thread
+            .activity_stack
+            .iter()
+            .map(|frame| frame.activity.as_str())
+            .collect();
+        assert_eq!(vec!["handling request", "parsing"], activities);
+        assert!(report
+            .threads
+            .iter()
+            .all(|other| other.name != "report_on_panic"));
+    }
+}
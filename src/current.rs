@@ -0,0 +1,163 @@
+use std::cell::RefCell;
+#[cfg(feature = "with_log")]
+use std::collections::BTreeMap;
+
+use super::status::RegisteredStatus;
+#[cfg(feature = "with_crash_report")]
+use super::ActivityFrame;
+use super::ThreadScope;
+use super::ThreadScopeActivityGuard;
+
+thread_local! {
+    static CURRENT_SCOPE: RefCell<Option<ThreadScope>> = const { RefCell::new(None) };
+}
+
+#[cfg(feature = "with_log")]
+thread_local! {
+    static CURRENT_IDENTITY: RefCell<Option<ThreadIdentity>> = const { RefCell::new(None) };
+}
+
+/// Identity of the current thread, cached at registration for the log wrapper.
+#[cfg(feature = "with_log")]
+pub(crate) struct ThreadIdentity {
+    pub(crate) id: u64,
+    pub(crate) labels: BTreeMap<String, String>,
+    pub(crate) name: String,
+}
+
+/// Set or clear the [`ThreadScope`] of the current thread from its registered status.
+///
+/// [`ThreadScope`]: struct.ThreadScope.html
+pub(crate) fn set_current(status: Option<&RegisteredStatus>) {
+    // Ignore errors if the thread local was already destroyed.
+    let scope = status.map(RegisteredStatus::scope);
+    let _ = CURRENT_SCOPE.try_with(|current| *current.borrow_mut() = scope);
+    #[cfg(feature = "with_log")]
+    {
+        let identity = status.map(|status| ThreadIdentity {
+            id: status.id(),
+            labels: status.labels().clone(),
+            name: status.name().to_string(),
+        });
+        let _ = CURRENT_IDENTITY.try_with(|current| *current.borrow_mut() = identity);
+    }
+}
+
+/// Invoke a function with the cached identity and the scope of the current thread, if registered.
+///
+/// The global registry is not locked so this can be called while logging.
+#[cfg(feature = "with_log")]
+pub(crate) fn with_current_identity<F, R>(f: F) -> Option<R>
+where
+    F: FnOnce(&ThreadIdentity, &ThreadScope) -> R,
+{
+    let scope = current()?;
+    CURRENT_IDENTITY
+        .try_with(|identity| {
+            let identity = identity.try_borrow().ok()?;
+            Some(f(identity.as_ref()?, &scope))
+        })
+        .ok()
+        .flatten()
+}
+
+/// Return the [`ThreadScope`] of the current thread, if the thread is registered.
+///
+/// [`ThreadScope`]: struct.ThreadScope.html
+pub fn current() -> Option<ThreadScope> {
+    CURRENT_SCOPE
+        .try_with(|current| current.borrow().clone())
+        .ok()
+        .flatten()
+}
+
+/// Collect the activity stack of the current thread without blocking or running user code.
+///
+/// Returns `None` if the thread is not registered or the stack can't be collected.
+#[cfg(feature = "with_crash_report")]
+pub(crate) fn try_untyped_activity_stack() -> Option<Vec<ActivityFrame>> {
+    CURRENT_SCOPE
+        .try_with(|current| {
+            let current = current.try_borrow().ok()?;
+            current.as_ref()?.activity_state().try_untyped_frames()
+        })
+        .ok()
+        .flatten()
+}
+
+/// Report the current thread activity, if the thread is registered.
+///
+/// See [`ThreadScope::activity`] for details.
+///
+/// [`ThreadScope::activity`]: struct.ThreadScope.html#method.activity
+pub fn activity<S: Into<String>>(activity: S) {
+    if let Some(scope) = current() {
+        scope.activity(activity);
+    }
+}
+
+/// Report the given activity for the duration of a scope, if the thread is registered.
+///
+/// See [`ThreadScope::scoped_activity`] for details.
+///
+/// [`ThreadScope::scoped_activity`]: struct.ThreadScope.html#method.scoped_activity
+pub fn scoped_activity<S: Into<String>>(activity: S) -> Option<ThreadScopeActivityGuard> {
+    current().map(|scope| scope.scoped_activity(activity))
+}
+
+/// Check if the current thread was requested to shutdown.
+///
+/// Threads that are not registered are never requested to shutdown.
+pub fn should_shutdown() -> bool {
+    current()
+        .map(|scope| scope.should_shutdown())
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::super::registered_threads;
+    use super::super::Builder;
+
+    #[test]
+    fn unregistered_thread() {
+        let thread = ::std::thread::spawn(|| {
+            super::activity("ignored");
+            (
+                super::current().is_none(),
+                super::scoped_activity("ignored").is_none(),
+                super::should_shutdown(),
+            )
+        });
+        let (current, scoped, shutdown) = thread.join().expect("the thread to stop");
+        assert!(current);
+        assert!(scoped);
+        assert!(!shutdown);
+    }
+
+    #[test]
+    fn registered_thread() {
+        let thread = Builder::new("current_registered_thread")
+            .spawn(|_| {
+                super::activity("reported without a scope");
+                while !super::should_shutdown() {
+                    ::std::thread::sleep(Duration::from_millis(10));
+                }
+            })
+            .expect("to spawn test thread");
+
+        // Give it a chance to register and collect list.
+        ::std::thread::sleep(Duration::from_millis(20));
+        let threads = registered_threads();
+        thread.request_shutdown();
+        thread.join().expect("the thread to stop");
+
+        let thread = threads
+            .into_iter()
+            .find(|t| t.name == "current_registered_thread")
+            .expect("test thread not found");
+        assert_eq!(Some("reported without a scope".into()), thread.activity);
+    }
+}
@@ -0,0 +1,191 @@
+use std::fmt::Write as FmtWrite;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use failure::ResultExt;
+use signal_hook::consts::SIGUSR1;
+use signal_hook::iterator::Handle;
+use signal_hook::iterator::Signals;
+
+use super::registered_threads;
+use super::table::format_age;
+use super::Builder;
+use super::ErrorKind;
+use super::Result;
+use super::Thread;
+use super::ThreadStatus;
+
+/// Destination of thread dumps.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DumpTarget {
+    /// Append dumps to the file at the given path, creating it if needed.
+    File(PathBuf),
+
+    /// Write dumps to the process standard error.
+    Stderr,
+}
+
+/// Write a dump of registered threads every time the process receives `SIGUSR1`.
+///
+/// Similar to `kill -3` for a JVM, `kill -USR1 <pid>` prints the name, ids, state
+/// and activity stack of each registered thread.
+///
+/// The signal handler does not format the dump itself: it only wakes up a dedicated
+/// `humthreads-dump` thread, which is registered like any other humthreads thread,
+/// so dumps are produced outside of the signal context.
+///
+/// Dropping the handle leaves the handler installed, use [`stop`] to remove it.
+///
+/// [`stop`]: #method.stop
+pub struct DumpOnSignal {
+    signals: Handle,
+    thread: Thread<()>,
+}
+
+impl DumpOnSignal {
+    /// Install the `SIGUSR1` handler and start the dump thread.
+    pub fn install(target: DumpTarget) -> Result<DumpOnSignal> {
+        let mut signals = Signals::new([SIGUSR1]).with_context(|_| ErrorKind::SignalDump)?;
+        let handle = signals.handle();
+        let thread = Builder::new("humthreads-dump")
+            .full_name("humthreads SIGUSR1 thread dump")
+            .spawn(move |scope| {
+                scope.idle();
+                for _ in signals.forever() {
+                    let _activity = scope.scoped_activity("writing thread dump");
+                    // There is nowhere to report the error to so dumps are best effort.
+                    let _ = write_dump(&target);
+                }
+            })?;
+        Ok(DumpOnSignal {
+            signals: handle,
+            thread,
+        })
+    }
+
+    /// Stop handling `SIGUSR1` and wait for the dump thread to exit.
+    ///
+    /// NOTE: once stopped `SIGUSR1` is ignored rather than handled with the default action.
+    pub fn stop(self) -> Result<()> {
+        self.signals.close();
+        self.thread.join()
+    }
+}
+
+/// Format a human readable dump of the given threads.
+///
+/// Activity stacks are listed from the innermost activity, with the time each activity
+/// has been in progress for.
+pub fn thread_dump(threads: &[ThreadStatus]) -> String {
+    let now = SystemTime::now();
+    let timestamp = now
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs())
+        .unwrap_or(0);
+    let mut dump = String::new();
+    let _ = writeln!(
+        dump,
+        "Thread dump of process {} at {} (unix time): {} registered threads",
+        ::std::process::id(),
+        timestamp,
+        threads.len()
+    );
+    for thread in threads {
+        let state = thread.state();
+        let _ = write!(dump, "\n\"{}\" id={}", thread.name, thread.id);
+        if let Some(os_id) = thread.os_id {
+            let _ = write!(dump, " tid={}", os_id);
+        }
+        if let Some(group) = thread.group.as_ref() {
+            let _ = write!(dump, " group={}", group);
+        }
+        let _ = writeln!(dump, " state={}", state);
+        for frame in thread.activity_stack.iter().rev() {
+            let age = now
+                .duration_since(frame.since)
+                .map(format_age)
+                .unwrap_or_else(|_| "-".into());
+            let _ = write!(dump, "    at {}", frame.activity);
+            if !frame.fields.is_empty() {
+                let fields: Vec<String> = frame
+                    .fields
+                    .iter()
+                    .map(|(key, value)| format!("{}={}", key, value))
+                    .collect();
+                let _ = write!(dump, " {{{}}}", fields.join(", "));
+            }
+            let _ = writeln!(dump, " (for {})", age);
+        }
+    }
+    dump
+}
+
+/// Write a dump of all registered threads to the target.
+fn write_dump(target: &DumpTarget) -> ::std::io::Result<()> {
+    let mut dump = thread_dump(&registered_threads());
+    dump.push('\n');
+    match target {
+        DumpTarget::File(path) => OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(path)?
+            .write_all(dump.as_bytes()),
+        DumpTarget::Stderr => ::std::io::stderr().write_all(dump.as_bytes()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use std::time::Instant;
+
+    use signal_hook::consts::SIGUSR1;
+
+    use super::super::Builder;
+    use super::DumpOnSignal;
+    use super::DumpTarget;
+
+    #[test]
+    fn dump_on_signal() {
+        let path = ::std::env::temp_dir().join(format!("humthreads-dump-{}", ::std::process::id()));
+        let _ = ::std::fs::remove_file(&path);
+        let dump = DumpOnSignal::install(DumpTarget::File(path.clone())).expect("to install");
+        let thread = Builder::new("dump_on_signal")
+            .spawn(|scope| {
+                let _outer = scope.scoped_activity_with("handling request", vec![("request", 17)]);
+                let _inner = scope.scoped_activity("waiting for database");
+                while !scope.should_shutdown() {
+                    ::std::thread::sleep(Duration::from_millis(10));
+                }
+            })
+            .expect("to spawn test thread");
+
+        // Give the thread a chance to register, request a dump and wait for it.
+        ::std::thread::sleep(Duration::from_millis(50));
+        signal_hook::low_level::raise(SIGUSR1).expect("to raise SIGUSR1");
+        let start = Instant::now();
+        let mut content = String::new();
+        while start.elapsed() < Duration::from_secs(2) && !content.ends_with("\n\n") {
+            ::std::thread::sleep(Duration::from_millis(10));
+            content = ::std::fs::read_to_string(&path).unwrap_or_default();
+        }
+        thread.request_shutdown();
+        thread.join().expect("the thread to stop");
+        dump.stop().expect("the dump thread to stop");
+        let _ = ::std::fs::remove_file(&path);
+
+        assert!(content.starts_with("Thread dump of process "));
+        let lines: Vec<&str> = content.lines().collect();
+        let header = lines
+            .iter()
+            .position(|line| line.starts_with("\"dump_on_signal\" id="))
+            .expect("thread not found in dump");
+        assert!(lines[header].ends_with(" state=busy"));
+        assert!(lines[header + 1].starts_with("    at waiting for database (for "));
+        assert!(lines[header + 2].starts_with("    at handling request {request=17} (for "));
+        assert!(content.contains("\"humthreads SIGUSR1 thread dump\" id="));
+    }
+}
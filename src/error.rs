@@ -1,3 +1,6 @@
+// The `Fail` derive expands to impl blocks nested in an anonymous const.
+#![allow(non_local_definitions)]
+
 use std::any::Any;
 use std::fmt;
 use std::sync::Mutex;
@@ -47,6 +50,15 @@ impl From<ErrorKind> for Error {
 /// Exhaustive list of possible errors emitted by this crate.
 #[derive(Debug, Fail)]
 pub enum ErrorKind {
+    #[fail(display = "thread is already registered")]
+    AlreadyRegistered,
+
+    #[fail(display = "unable to set up crash reports")]
+    CrashReport,
+
+    #[fail(display = "unable to start the HTTP introspection server")]
+    HttpServer,
+
     #[fail(display = "unable to join thread")]
     Join(Mutex<Box<dyn Any + Send + 'static>>),
 
@@ -56,6 +68,18 @@ pub enum ErrorKind {
     #[fail(display = "thread already joined")]
     JoinedAlready,
 
+    #[fail(display = "unable to install the thread dump signal handler")]
+    SignalDump,
+
+    #[fail(display = "unable to communicate with the introspection socket")]
+    SocketClient,
+
+    #[fail(display = "introspection socket returned an error: {}", _0)]
+    SocketReply(String),
+
+    #[fail(display = "unable to start the introspection socket server")]
+    SocketServer,
+
     #[fail(display = "unable to spawn new thread")]
     Spawn,
 }
@@ -0,0 +1,295 @@
+use std::collections::HashMap;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Condvar;
+use std::sync::Mutex;
+use std::sync::PoisonError;
+use std::time::Duration;
+use std::time::Instant;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+#[cfg(feature = "with_tracing")]
+use super::registry::registered_names;
+use super::registry::registered_threads_filter;
+use super::status::RegisteredStatus;
+use super::ErrorKind;
+use super::Result;
+use super::ThreadStatus;
+
+/// Named set of threads that can be inspected and controlled together.
+///
+/// Threads are added to a group when they are spawned with [`Builder::group`].
+/// Groups are cheap to clone and all clones refer to the same set of threads.
+///
+/// [`Builder::group`]: struct.Builder.html#method.group
+#[derive(Clone)]
+pub struct ThreadGroup {
+    inner: Arc<GroupInner>,
+}
+
+impl ThreadGroup {
+    pub fn new<S: Into<String>>(name: S) -> ThreadGroup {
+        let inner = GroupInner {
+            exit: Condvar::new(),
+            members: Mutex::new(GroupMembers::default()),
+            name: name.into(),
+        };
+        ThreadGroup {
+            inner: Arc::new(inner),
+        }
+    }
+
+    /// Wait for all threads in the group to exit, but not for longer than `timeout`.
+    ///
+    /// NOTE: this only waits for threads to exit, results are still collected
+    /// with the join methods of each thread handle.
+    pub fn join_timeout(&self, timeout: Duration) -> Result<()> {
+        let deadline = Instant::now() + timeout;
+        let mut members = self
+            .inner
+            .members
+            .lock()
+            .expect("ThreadGroup::members lock poisoned");
+        while !members.running.is_empty() {
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(ErrorKind::JoinTimeout.into());
+            }
+            members = self
+                .inner
+                .exit
+                .wait_timeout(members, deadline - now)
+                .expect("ThreadGroup::members lock poisoned")
+                .0;
+        }
+        Ok(())
+    }
+
+    /// Name of the group, as reported in [`ThreadStatus`].
+    ///
+    /// [`ThreadStatus`]: struct.ThreadStatus.html
+    pub fn name(&self) -> &str {
+        &self.inner.name
+    }
+
+    /// Signal all running threads in the group they should terminate as soon as possible.
+    pub fn request_shutdown(&self) {
+        let members = self
+            .inner
+            .members
+            .lock()
+            .expect("ThreadGroup::members lock poisoned");
+        for shutdown in members.running.values() {
+            shutdown.store(true, Ordering::Relaxed);
+        }
+        drop(members);
+        #[cfg(feature = "with_tracing")]
+        for (id, name) in registered_names(|status| self.is_member(status)) {
+            super::lifecycle::shutdown_requested(id, &name);
+        }
+    }
+
+    /// Aggregate counts of threads in the group by state.
+    pub fn status(&self) -> ThreadGroupStatus {
+        let members = self
+            .inner
+            .members
+            .lock()
+            .expect("ThreadGroup::members lock poisoned");
+        let shutting_down = members
+            .running
+            .values()
+            .filter(|shutdown| shutdown.load(Ordering::Relaxed))
+            .count();
+        ThreadGroupStatus {
+            exited: members.exited,
+            panicked: members.panicked,
+            running: members.running.len() - shutting_down,
+            shutting_down,
+        }
+    }
+
+    /// Return a snapshot of the current status of registered threads in the group.
+    pub fn threads(&self) -> Vec<ThreadStatus> {
+        registered_threads_filter(|status| self.is_member(status))
+    }
+
+    /// Check if a registered thread belongs to the group.
+    fn is_member(&self, status: &RegisteredStatus) -> bool {
+        status
+            .group()
+            .map(|group| Arc::ptr_eq(&group.inner, &self.inner))
+            .unwrap_or(false)
+    }
+
+    /// Add a new running thread to the group.
+    pub(crate) fn join_group(&self, shutdown: Arc<AtomicBool>) -> GroupMembership {
+        let mut members = self
+            .inner
+            .members
+            .lock()
+            .expect("ThreadGroup::members lock poisoned");
+        let member = members.next_member;
+        members.next_member += 1;
+        members.running.insert(member, shutdown);
+        GroupMembership {
+            group: self.clone(),
+            left: false,
+            member,
+        }
+    }
+}
+
+/// Aggregate counts of threads in a [`ThreadGroup`] by state.
+///
+/// [`ThreadGroup`]: struct.ThreadGroup.html
+#[derive(Clone, Copy, Default, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct ThreadGroupStatus {
+    /// Number of threads that exited normally.
+    pub exited: usize,
+
+    /// Number of threads that exited because of a panic.
+    pub panicked: usize,
+
+    /// Number of threads still running and not requested to shutdown.
+    pub running: usize,
+
+    /// Number of threads still running after they were requested to shutdown.
+    pub shutting_down: usize,
+}
+
+/// Membership of a thread in a group, updated when the thread exits.
+///
+/// Memberships dropped without calling `exit`, because the thread failed to spawn,
+/// leave the group without being counted as exited.
+pub(crate) struct GroupMembership {
+    group: ThreadGroup,
+    left: bool,
+    member: u64,
+}
+
+impl GroupMembership {
+    /// Record the exit of the member thread and wake up anyone waiting on the group.
+    pub(crate) fn exit(mut self, panicked: bool) {
+        self.leave(Some(panicked));
+    }
+
+    /// Remove the member from the running threads, counting it if it exited.
+    fn leave(&mut self, panicked: Option<bool>) {
+        self.left = true;
+        let mut members = self
+            .group
+            .inner
+            .members
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        members.running.remove(&self.member);
+        match panicked {
+            Some(true) => members.panicked += 1,
+            Some(false) => members.exited += 1,
+            None => (),
+        }
+        self.group.inner.exit.notify_all();
+    }
+}
+
+impl Drop for GroupMembership {
+    fn drop(&mut self) {
+        if !self.left {
+            self.leave(None);
+        }
+    }
+}
+
+struct GroupInner {
+    exit: Condvar,
+    members: Mutex<GroupMembers>,
+    name: String,
+}
+
+/// Track running threads by member id and only count exited ones.
+#[derive(Default)]
+struct GroupMembers {
+    exited: usize,
+    next_member: u64,
+    panicked: usize,
+    running: HashMap<u64, Arc<AtomicBool>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::super::Builder;
+    use super::ThreadGroup;
+
+    #[test]
+    fn counts_and_shutdown() {
+        let group = ThreadGroup::new("counts_and_shutdown");
+        let mut threads = Vec::new();
+        for idx in 0..3 {
+            let thread = Builder::new(format!("counts_and_shutdown_{}", idx))
+                .group(&group)
+                .spawn(|scope| loop {
+                    ::std::thread::sleep(Duration::from_millis(10));
+                    if scope.should_shutdown() {
+                        break;
+                    }
+                })
+                .expect("to spawn test thread");
+            threads.push(thread);
+        }
+        let panic = Builder::new("counts_and_shutdown_panic")
+            .group(&group)
+            .spawn(|_| panic!("this panic is expected"))
+            .expect("to spawn test thread");
+        assert!(panic.join().is_err());
+
+        // Give threads a chance to register and inspect the group.
+        ::std::thread::sleep(Duration::from_millis(20));
+        let members = group.threads();
+        let running = group.status();
+        group.request_shutdown();
+        group
+            .join_timeout(Duration::from_millis(100))
+            .expect("the group to stop");
+        let stopped = group.status();
+
+        // Assert test results.
+        assert_eq!(3, members.len());
+        assert!(members
+            .iter()
+            .all(|thread| thread.group.as_deref() == Some("counts_and_shutdown")));
+        assert_eq!(3, running.running);
+        assert_eq!(1, running.panicked);
+        assert_eq!(0, stopped.running);
+        assert_eq!(0, stopped.shutting_down);
+        assert_eq!(3, stopped.exited);
+        assert_eq!(1, stopped.panicked);
+        for thread in threads {
+            thread.join().expect("the thread to stop");
+        }
+    }
+
+    #[test]
+    fn join_timeout() {
+        let group = ThreadGroup::new("join_timeout");
+        let thread = Builder::new("group_join_timeout")
+            .group(&group)
+            .spawn(|scope| loop {
+                ::std::thread::sleep(Duration::from_millis(10));
+                if scope.should_shutdown() {
+                    break;
+                }
+            })
+            .expect("to spawn test thread");
+        assert!(group.join_timeout(Duration::from_millis(20)).is_err());
+        assert_eq!(1, group.status().running);
+        group.request_shutdown();
+        assert_eq!(1, group.status().shutting_down);
+        thread.join().expect("the thread to stop");
+    }
+}
@@ -9,6 +9,7 @@ use crossbeam_channel::RecvTimeoutError;
 use crossbeam_channel::Select;
 use crossbeam_channel::SelectedOperation;
 
+use super::super::lifecycle;
 use super::super::ErrorKind;
 use super::super::Result;
 
@@ -22,6 +23,8 @@ pub struct MapThread<T: Send + 'static> {
     // Thread type is not Sync (therefore two methods can't be called at once).
     join: RefCell<Option<MapThreadFn<T>>>,
     join_check: Receiver<()>,
+    id: u64,
+    name: String,
     shutdown: Arc<AtomicBool>,
 }
 
@@ -29,6 +32,8 @@ impl<T: Send + 'static> MapThread<T> {
     pub(crate) fn new<F>(
         join: F,
         join_check: Receiver<()>,
+        id: u64,
+        name: String,
         shutdown: Arc<AtomicBool>,
     ) -> MapThread<T>
     where
@@ -39,6 +44,8 @@ impl<T: Send + 'static> MapThread<T> {
         MapThread {
             join,
             join_check,
+            id,
+            name,
             shutdown,
         }
     }
@@ -78,6 +85,7 @@ impl<T: Send + 'static> MapThread<T> {
     /// [`Thread::request_shutdown`]: struct.Thread.html#method.request_shutdown
     pub fn request_shutdown(&self) {
         self.shutdown.store(true, Ordering::Relaxed);
+        lifecycle::shutdown_requested(self.id, &self.name);
     }
 
     /// Add the thread to a [`Select`] set.
@@ -115,7 +123,7 @@ mod tests {
             .map(|_| true)
             .join()
             .expect("failed to join thread");
-        assert_eq!(true, flag);
+        assert!(flag);
     }
 
     #[test]
@@ -131,7 +139,7 @@ mod tests {
             .map(|_| true);
         thread.request_shutdown();
         let flag = thread.join().expect("the thread to stop");
-        assert_eq!(true, flag);
+        assert!(flag);
     }
 
     #[test]
@@ -196,7 +204,7 @@ mod tests {
         let idx = op.index();
         let result = thread.select_join(op);
         assert_eq!(0, idx);
-        assert_eq!(true, result.is_err());
+        assert!(result.is_err());
     }
 
     #[test]
@@ -1,4 +1,5 @@
 use std::cell::RefCell;
+use std::marker::PhantomData;
 use std::sync::atomic::AtomicBool;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
@@ -12,8 +13,26 @@ use crossbeam_channel::Select;
 use crossbeam_channel::SelectedOperation;
 use crossbeam_channel::Sender;
 
+use crate::activity::collect_fields;
+use crate::activity::ActivityFields;
+use crate::activity::ActivityFrame;
+use crate::activity::ActivityName;
+use crate::activity::ActivityReport;
+use crate::activity::ActivityState;
+use crate::activity::ActivityValue;
+use crate::activity::StackFrame;
+use crate::current::set_current;
+use crate::group::GroupMembership;
+use crate::inspect::ThreadInspectors;
+use crate::inspect::DEFAULT_INSPECT_BUDGET;
+use crate::lifecycle;
+use crate::metrics::Counter;
+use crate::metrics::Gauge;
+use crate::metrics::ThreadMetrics;
+use crate::progress::ProgressState;
 use crate::registry::deregister_thread;
 use crate::registry::register_thread;
+use crate::registry::thread_id;
 use crate::status::RegisteredStatus;
 use crate::ErrorKind;
 use crate::Result;
@@ -26,18 +45,24 @@ pub use self::map::MapThread;
 ///
 /// When this structure is dropped (falls out of scope), the thread reported activity
 /// will be reverted back to what it was when the guard was created.
+///
+/// The duration of the scope is recorded in [`ThreadStatus::latencies`] under the name of
+/// the activity at the time the guard is dropped.
+///
+/// Guards are expected to be dropped in the reverse order they are created in.
+/// Guards dropped out of order only end their own activity and are counted in
+/// [`ThreadStatus::activity_out_of_order`].
+///
+/// [`ThreadStatus::activity_out_of_order`]: struct.ThreadStatus.html#structfield.activity_out_of_order
+/// [`ThreadStatus::latencies`]: struct.ThreadStatus.html#structfield.latencies
 pub struct ThreadScopeActivityGuard {
-    activity: Arc<Mutex<Option<String>>>,
-    current: Option<String>,
+    activity: Arc<ActivityState>,
+    scope: u64,
 }
 
 impl Drop for ThreadScopeActivityGuard {
     fn drop(&mut self) {
-        let mut guard = self
-            .activity
-            .lock()
-            .expect("ThreadScopeActivityGuard::activity lock poisoned");
-        *guard = self.current.take();
+        self.activity.pop(self.scope);
     }
 }
 
@@ -50,6 +75,8 @@ pub struct Thread<T: Send + 'static> {
     // Thread type is not Sync (therefore two methods can't be called at once).
     join: RefCell<Option<JoinHandle<T>>>,
     join_check: Receiver<()>,
+    id: u64,
+    name: String,
     shutdown: Arc<AtomicBool>,
 }
 
@@ -57,12 +84,16 @@ impl<T: Send + 'static> Thread<T> {
     pub(crate) fn new(
         join: JoinHandle<T>,
         join_check: Receiver<()>,
+        name: String,
         shutdown: Arc<AtomicBool>,
     ) -> Thread<T> {
+        let id = thread_id(join.thread().id());
         let join = RefCell::new(Some(join));
         Thread {
             join,
             join_check,
+            id,
+            name,
             shutdown,
         }
     }
@@ -124,7 +155,7 @@ impl<T: Send + 'static> Thread<T> {
                 .map_err(|error| ErrorKind::Join(Mutex::new(error)).into())
                 .map(|r| f(r))
         };
-        MapThread::new(join, self.join_check, self.shutdown)
+        MapThread::new(join, self.join_check, self.id, self.name, self.shutdown)
     }
 
     /// Signal the thread is should terminate as soon as possible.
@@ -133,6 +164,7 @@ impl<T: Send + 'static> Thread<T> {
     /// periodiaclly check if it needs to terminate or not.
     pub fn request_shutdown(&self) {
         self.shutdown.store(true, Ordering::Relaxed);
+        lifecycle::shutdown_requested(self.id, &self.name);
     }
 
     /// Add the thread to a [`Select`] set.
@@ -160,17 +192,42 @@ impl<T: Send + 'static> Thread<T> {
 /// Each `ThreadScope` is an interface to advanced theard API below.
 ///
 /// [`ThreadScope`]: struct.ThreadScope.html
+#[derive(Clone)]
 pub struct ThreadScope {
-    activity: Arc<Mutex<Option<String>>>,
+    activity: Arc<ActivityState>,
+    inspectors: Arc<ThreadInspectors>,
+    metrics: Arc<ThreadMetrics>,
+    progress: Arc<ProgressState>,
     shutdown: Arc<AtomicBool>,
 }
 
 impl ThreadScope {
     pub(crate) fn new(
-        activity: Arc<Mutex<Option<String>>>,
+        activity: Arc<ActivityState>,
+        inspectors: Arc<ThreadInspectors>,
+        metrics: Arc<ThreadMetrics>,
+        progress: Arc<ProgressState>,
         shutdown: Arc<AtomicBool>,
     ) -> ThreadScope {
-        ThreadScope { activity, shutdown }
+        ThreadScope {
+            activity,
+            inspectors,
+            metrics,
+            progress,
+            shutdown,
+        }
+    }
+
+    /// Describe the innermost activity of the thread, if any.
+    #[cfg(feature = "with_log")]
+    pub(crate) fn innermost_activity(&self) -> Option<String> {
+        self.activity.innermost()
+    }
+
+    /// Access the activity tracking state of the thread.
+    #[cfg(feature = "with_crash_report")]
+    pub(crate) fn activity_state(&self) -> &ActivityState {
+        &self.activity
     }
 
     /// Report the current thread activity.
@@ -179,45 +236,257 @@ impl ThreadScope {
     /// The main use case is to aid application end users monitor, debug, and understand
     /// complex software they use and operate but not implement.
     pub fn activity<S: Into<String>>(&self, activity: S) {
-        let activity = activity.into();
-        let mut guard = self
-            .activity
-            .lock()
-            .expect("ThreadScope::activity lock poisoned");
-        *guard = Some(activity);
+        let frame = ActivityFrame::new(activity.into(), ActivityFields::new());
+        self.activity.set(Some(frame.into()));
+    }
+
+    /// Report the current thread activity along with structured key-value fields.
+    ///
+    /// Fields are exposed by the introspection API and can be used to filter threads
+    /// without parsing the activity description.
+    ///
+    /// ```
+    /// # use humthreads::Builder;
+    /// # let thread = Builder::new("activity_with").spawn(|scope| {
+    /// scope.activity_with("processing task", vec![("task", 4)]);
+    /// # }).unwrap();
+    /// # thread.join().unwrap();
+    /// ```
+    pub fn activity_with<S, F, K, V>(&self, activity: S, fields: F)
+    where
+        S: Into<String>,
+        F: IntoIterator<Item = (K, V)>,
+        K: Into<String>,
+        V: Into<ActivityValue>,
+    {
+        let frame = ActivityFrame::new(activity.into(), collect_fields(fields));
+        self.activity.set(Some(frame.into()));
+    }
+
+    /// Record `count` more units of work as completed towards the reported progress.
+    ///
+    /// See [`ThreadScope::progress`] for details.
+    ///
+    /// [`ThreadScope::progress`]: struct.ThreadScope.html#method.progress
+    pub fn advance(&self, count: u64) {
+        self.progress.advance(count);
+    }
+
+    /// Stop reporting progress.
+    pub fn clear_progress(&self) {
+        self.progress.clear();
+    }
+
+    /// Return a handle to the named counter published by this thread.
+    ///
+    /// Counters are created the first time they are requested and are included in
+    /// [`ThreadStatus`] snapshots.
+    /// Handles can be cached and updated without locking.
+    ///
+    /// ```
+    /// # use humthreads::Builder;
+    /// # let thread = Builder::new("counter").spawn(|scope| {
+    /// let processed = scope.counter("messages processed");
+    /// let depth = scope.gauge("queue depth");
+    /// for message in 0..10 {
+    ///     depth.set(10 - message);
+    ///     processed.inc();
+    /// }
+    /// # }).unwrap();
+    /// # thread.join().unwrap();
+    /// ```
+    ///
+    /// [`ThreadStatus`]: struct.ThreadStatus.html
+    pub fn counter<S: Into<String>>(&self, name: S) -> Counter {
+        self.metrics.counter(name.into())
+    }
+
+    /// Return a handle to the named gauge published by this thread.
+    ///
+    /// See [`ThreadScope::counter`] for details.
+    ///
+    /// [`ThreadScope::counter`]: struct.ThreadScope.html#method.counter
+    pub fn gauge<S: Into<String>>(&self, name: S) -> Gauge {
+        self.metrics.gauge(name.into())
     }
 
     /// Clear any previously reported activity.
     pub fn idle(&self) {
-        let mut guard = self
-            .activity
-            .lock()
-            .expect("ThreadScope::activity lock poisoned");
-        *guard = None;
+        self.activity.set(None);
+    }
+
+    /// Register a callback to report additional fields when a snapshot of the thread is taken.
+    ///
+    /// Use this for state that is expensive to report continuously but cheap to read
+    /// on demand, such as the length of a buffer.
+    /// Returned fields are exposed in [`ThreadStatus::inspected`].
+    ///
+    /// Callbacks are invoked on a `humthreads-inspect` worker thread on behalf of the thread
+    /// requesting the snapshot, one after the other, and must be quick.
+    /// Callbacks that panic or take longer than 10 milliseconds are disabled and the issue
+    /// is reported in [`ThreadStatus::inspect_errors`] instead.
+    /// Disabled callbacks are never invoked again: register a new callback to retry.
+    /// Callbacks that block are abandoned once their budget expires so they can't block
+    /// snapshots, and panic messages name the worker thread.
+    /// Use [`ThreadScope::on_inspect_with_budget`] to change the time limit.
+    ///
+    /// ```
+    /// # use std::sync::Arc;
+    /// # use std::sync::Mutex;
+    /// # use humthreads::Builder;
+    /// use humthreads::ActivityFields;
+    ///
+    /// # let thread = Builder::new("on_inspect").spawn(|scope| {
+    /// let buffer = Arc::new(Mutex::new(Vec::<u8>::new()));
+    /// let inspect_buffer = Arc::clone(&buffer);
+    /// scope.on_inspect(move || {
+    ///     let mut fields = ActivityFields::new();
+    ///     let length = inspect_buffer.lock().unwrap().len() as i64;
+    ///     fields.insert("buffer length".into(), length.into());
+    ///     fields
+    /// });
+    /// # }).unwrap();
+    /// # thread.join().unwrap();
+    /// ```
+    ///
+    /// [`ThreadScope::on_inspect_with_budget`]: struct.ThreadScope.html#method.on_inspect_with_budget
+    /// [`ThreadStatus::inspect_errors`]: struct.ThreadStatus.html#structfield.inspect_errors
+    /// [`ThreadStatus::inspected`]: struct.ThreadStatus.html#structfield.inspected
+    pub fn on_inspect<F>(&self, callback: F)
+    where
+        F: Fn() -> ActivityFields + Send + Sync + 'static,
+    {
+        self.on_inspect_with_budget(DEFAULT_INSPECT_BUDGET, callback);
+    }
+
+    /// Same as [`ThreadScope::on_inspect`] but with a custom time budget for the callback.
+    ///
+    /// [`ThreadScope::on_inspect`]: struct.ThreadScope.html#method.on_inspect
+    pub fn on_inspect_with_budget<F>(&self, budget: Duration, callback: F)
+    where
+        F: Fn() -> ActivityFields + Send + Sync + 'static,
+    {
+        self.inspectors.add(budget, Box::new(callback));
+    }
+
+    /// Start reporting progress towards the completion of `total` units of work.
+    ///
+    /// Completed work is reported with [`ThreadScope::advance`] and resets to 0 every time
+    /// this method is called.
+    /// Throughput and estimated completion time are computed from the time this method
+    /// is called, regardless of the activities reported while the work progresses.
+    ///
+    /// ```
+    /// # use humthreads::Builder;
+    /// # let thread = Builder::new("progress").spawn(|scope| {
+    /// let rows = vec![1, 2, 3];
+    /// scope.activity("migrating rows");
+    /// scope.progress(rows.len() as u64);
+    /// for _row in rows {
+    ///     // Migrate the row ...
+    ///     scope.advance(1);
+    /// }
+    /// # }).unwrap();
+    /// # thread.join().unwrap();
+    /// ```
+    ///
+    /// [`ThreadScope::advance`]: struct.ThreadScope.html#method.advance
+    pub fn progress(&self, total: u64) {
+        self.progress.start(total);
     }
 
     /// Report the given activity for the duration of a scope.
     ///
     /// The scope is considered over once the returned [`ThreadScopeActivityGuard`] is dropped.
+    /// Scoped activities nest and the full stack of activities is available in [`ThreadStatus`].
     ///
     /// [`ThreadScopeActivityGuard`]: struct.ThreadScopeActivityGuard.html
+    /// [`ThreadStatus`]: struct.ThreadStatus.html
     pub fn scoped_activity<S: Into<String>>(&self, activity: S) -> ThreadScopeActivityGuard {
-        let activity = activity.into();
-        let mut guard = self
-            .activity
-            .lock()
-            .expect("ThreadScope::activity lock poisoned");
-        let current: Option<String> = guard.clone();
-        *guard = Some(activity);
-        drop(guard);
-        let activity = Arc::clone(&self.activity);
-        ThreadScopeActivityGuard { activity, current }
+        self.scoped_frame(ActivityFrame::new(activity.into(), ActivityFields::new()))
+    }
+
+    /// Report the given activity, with structured fields, for the duration of a scope.
+    ///
+    /// See [`ThreadScope::activity_with`] and [`ThreadScope::scoped_activity`] for details.
+    ///
+    /// [`ThreadScope::activity_with`]: struct.ThreadScope.html#method.activity_with
+    /// [`ThreadScope::scoped_activity`]: struct.ThreadScope.html#method.scoped_activity
+    pub fn scoped_activity_with<S, F, K, V>(
+        &self,
+        activity: S,
+        fields: F,
+    ) -> ThreadScopeActivityGuard
+    where
+        S: Into<String>,
+        F: IntoIterator<Item = (K, V)>,
+        K: Into<String>,
+        V: Into<ActivityValue>,
+    {
+        self.scoped_frame(ActivityFrame::new(activity.into(), collect_fields(fields)))
+    }
+
+    /// Report the current thread activity without allocations or locks.
+    ///
+    /// This is the same as [`ThreadScope::activity`] but only accepts `&'static str`s
+    /// or [`ActivityName`]s interned ahead of time.
+    /// Use this method when reporting activity in hot loops.
+    ///
+    /// ```
+    /// # use humthreads::Builder;
+    /// use humthreads::ActivityName;
+    ///
+    /// # let thread = Builder::new("static_activity").spawn(|scope| {
+    /// let topic = "events";
+    /// let consuming = ActivityName::intern(&format!("consuming from {}", topic));
+    /// for _message in 0..1000 {
+    ///     scope.static_activity(consuming);
+    ///     // Consume the message ...
+    ///     scope.static_activity("waiting for messages");
+    /// }
+    /// # }).unwrap();
+    /// # thread.join().unwrap();
+    /// ```
+    ///
+    /// [`ActivityName`]: struct.ActivityName.html
+    /// [`ThreadScope::activity`]: struct.ThreadScope.html#method.activity
+    pub fn static_activity<A: Into<ActivityName>>(&self, activity: A) {
+        self.activity.set_static(activity.into());
+    }
+
+    /// Report the current thread activity as a value of a domain type.
+    ///
+    /// The value is stored as is and only rendered with [`ActivityReport`] when
+    /// the introspection API requests a snapshot of registered threads.
+    /// Snapshots render activities after the registry is unlocked.
+    ///
+    /// NOTE: [`ActivityReport::describe`] is also called by the reporting thread,
+    /// when the activity ends or is replaced, if the thread records its history
+    /// ([`Builder::history`]), tracks time by activity ([`Builder::track_activity_time`]),
+    /// or a trace is being recorded.
+    /// Typed activities that replace a scoped activity are also described when the scope
+    /// ends, to record its latency.
+    /// Keep `describe` cheap if any of these features are used.
+    ///
+    /// [`ActivityReport::describe`]: trait.ActivityReport.html#tymethod.describe
+    /// [`Builder::history`]: struct.Builder.html#method.history
+    /// [`Builder::track_activity_time`]: struct.Builder.html#method.track_activity_time
+    /// [`ActivityReport`]: trait.ActivityReport.html
+    pub fn typed_activity<A: ActivityReport>(&self, activity: A) {
+        let frame = StackFrame::typed(Arc::new(activity));
+        self.activity.set(Some(frame));
     }
 
     /// Check if the thread was requested to shutdown.
     pub fn should_shutdown(&self) -> bool {
         self.shutdown.load(Ordering::Relaxed)
     }
+
+    fn scoped_frame(&self, frame: ActivityFrame) -> ThreadScopeActivityGuard {
+        let scope = self.activity.push(frame.into());
+        let activity = Arc::clone(&self.activity);
+        ThreadScopeActivityGuard { activity, scope }
+    }
 }
 
 /// Thread lifecycle guard.
@@ -234,21 +503,64 @@ impl ThreadScope {
 /// [`ThreadScope`]: struct.ThreadScope.html
 pub(crate) struct ThreadGuard {
     id: u64,
-    join_check: Sender<()>,
+    join_check: Option<Sender<()>>,
+    membership: Option<GroupMembership>,
 }
 
 impl ThreadGuard {
-    pub(crate) fn new(id: u64, join_check: Sender<()>, status: RegisteredStatus) -> ThreadGuard {
+    pub(crate) fn new(
+        id: u64,
+        join_check: Option<Sender<()>>,
+        status: RegisteredStatus,
+        membership: Option<GroupMembership>,
+    ) -> ThreadGuard {
         register_thread(id, status);
-        ThreadGuard { id, join_check }
+        ThreadGuard {
+            id,
+            join_check,
+            membership,
+        }
     }
 }
 
 impl Drop for ThreadGuard {
     fn drop(&mut self) {
+        let panicked = ::std::thread::panicking();
+        set_current(None);
+        #[cfg(feature = "with_crash_report")]
+        if panicked {
+            crate::crash::thread_panicked(self.id);
+        }
+        deregister_thread(self.id, panicked);
+        if let Some(membership) = self.membership.take() {
+            membership.exit(panicked);
+        }
         // Try to signal the parent thread we shut down but ignore errors.
-        let _ = self.join_check.try_send(());
-        deregister_thread(self.id);
+        if let Some(join_check) = self.join_check.as_ref() {
+            let _ = join_check.try_send(());
+        }
+    }
+}
+
+/// Registration of a thread not spawned by a [`Builder`].
+///
+/// Returned by [`register_current_thread`], the thread is deregistered when this guard is dropped.
+/// The guard must be dropped by the thread it registered.
+///
+/// [`Builder`]: struct.Builder.html
+/// [`register_current_thread`]: fn.register_current_thread.html
+pub struct RegisteredThreadGuard {
+    _guard: ThreadGuard,
+    // Prevent the guard from being sent to other threads.
+    _thread: PhantomData<*const ()>,
+}
+
+impl RegisteredThreadGuard {
+    pub(crate) fn new(guard: ThreadGuard) -> RegisteredThreadGuard {
+        RegisteredThreadGuard {
+            _guard: guard,
+            _thread: PhantomData,
+        }
     }
 }
 
@@ -440,6 +752,12 @@ mod tests {
             .find(|t| t.name == "scoped_activity")
             .expect("test thread not found");
         assert_eq!(Some("scope2".into()), status.activity);
+        let stack: Vec<String> = status
+            .activity_stack
+            .into_iter()
+            .map(|frame| frame.activity)
+            .collect();
+        assert_eq!(vec!["scope1", "scope2"], stack);
         let status = scope2_out
             .into_iter()
             .find(|t| t.name == "scoped_activity")
@@ -520,7 +838,7 @@ mod tests {
         let idx = op.index();
         let result = thread.select_join(op);
         assert_eq!(0, idx);
-        assert_eq!(true, result.is_err());
+        assert!(result.is_err());
     }
 
     #[test]
@@ -0,0 +1,99 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+/// Public view of an activity a thread completed.
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+pub struct ActivityRecord {
+    /// Description of the activity.
+    pub activity: String,
+
+    /// Time the activity ended, either because it was replaced or because its scope ended.
+    pub ended: SystemTime,
+
+    /// Time the activity was reported.
+    pub started: SystemTime,
+}
+
+/// Internal ring buffer of the most recent activities completed by a thread.
+pub(crate) struct ThreadHistory {
+    capacity: usize,
+    records: Mutex<VecDeque<ActivityRecord>>,
+}
+
+impl ThreadHistory {
+    /// Create a history of at most `capacity` records, or a disabled history if `capacity` is 0.
+    pub(crate) fn new(capacity: usize) -> ThreadHistory {
+        ThreadHistory {
+            capacity,
+            records: Mutex::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    /// Check if activities should be recorded at all.
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    /// Record the end of an activity, dropping the oldest record if needed.
+    pub(crate) fn record(&self, activity: String, started: SystemTime) {
+        if !self.is_enabled() {
+            return;
+        }
+        let record = ActivityRecord {
+            activity,
+            ended: SystemTime::now(),
+            started,
+        };
+        let mut records = self
+            .records
+            .lock()
+            .expect("ThreadHistory::records lock poisoned");
+        if records.len() == self.capacity {
+            records.pop_front();
+        }
+        records.push_back(record);
+    }
+
+    /// Recorded activities, from the oldest to the most recent.
+    pub(crate) fn records(&self) -> Vec<ActivityRecord> {
+        self.records
+            .lock()
+            .expect("ThreadHistory::records lock poisoned")
+            .iter()
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+
+    use super::ThreadHistory;
+
+    #[test]
+    fn disabled() {
+        let history = ThreadHistory::new(0);
+        history.record("ignored".into(), SystemTime::now());
+        assert!(!history.is_enabled());
+        assert!(history.records().is_empty());
+    }
+
+    #[test]
+    fn keeps_most_recent() {
+        let history = ThreadHistory::new(2);
+        for activity in ["first", "second", "third"] {
+            history.record(activity.into(), SystemTime::now());
+        }
+        let activities: Vec<String> = history
+            .records()
+            .into_iter()
+            .map(|record| record.activity)
+            .collect();
+        assert_eq!(vec!["second", "third"], activities);
+    }
+}
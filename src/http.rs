@@ -0,0 +1,309 @@
+use std::io::ErrorKind as IoErrorKind;
+use std::io::Read;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::net::TcpListener;
+use std::net::TcpStream;
+use std::net::ToSocketAddrs;
+use std::sync::atomic::AtomicUsize;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+
+use failure::ResultExt;
+
+use super::connections::ConnectionSlot;
+use super::prometheus_metrics;
+use super::registered_threads;
+use super::text_table;
+use super::Builder;
+use super::ErrorKind;
+use super::Result;
+use super::Thread;
+use super::ThreadQuery;
+use super::ThreadScope;
+
+/// Time to wait between checks for new connections and shutdown requests.
+const ACCEPT_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Maximum number of connections handled at the same time, others are dropped.
+const MAX_CONNECTIONS: usize = 16;
+
+/// Maximum size of the request line and headers.
+const MAX_REQUEST_SIZE: usize = 8 * 1024;
+
+/// Total time a client has to send its request before the connection is dropped.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Minimal HTTP server exposing the thread registry.
+///
+/// The server accepts connections on its own `humthreads-http` thread, which is registered
+/// like any other humthreads thread, and handles each connection on a short lived
+/// `humthreads-http-conn` thread so slow clients can't block other requests.
+/// Clients have 5 seconds to send their request and at most 16 connections are handled
+/// at the same time.
+/// The server is stopped when the `HttpServer` is dropped.
+/// The following endpoints are available:
+///
+///   * `/threads`: snapshot of [`registered_threads`] as JSON,
+///     add `?cpu_time=true` to include the CPU time of threads.
+///   * `/threads.txt`: snapshot of registered threads as a [`text_table`].
+///   * `/metrics`: metrics in the Prometheus text format (see [`prometheus_metrics`]).
+///
+/// The server is meant for local introspection and has no authentication:
+/// bind it to a loopback or otherwise private address.
+///
+/// [`prometheus_metrics`]: fn.prometheus_metrics.html
+/// [`registered_threads`]: fn.registered_threads.html
+/// [`text_table`]: fn.text_table.html
+pub struct HttpServer {
+    address: SocketAddr,
+    thread: Option<Thread<()>>,
+}
+
+impl HttpServer {
+    /// Bind the given address and start serving requests in a background thread.
+    ///
+    /// Bind to port 0 to have the OS pick a free port and use [`local_addr`] to find it.
+    ///
+    /// [`local_addr`]: #method.local_addr
+    pub fn start<A: ToSocketAddrs>(address: A) -> Result<HttpServer> {
+        let listener = TcpListener::bind(address).with_context(|_| ErrorKind::HttpServer)?;
+        let address = listener
+            .local_addr()
+            .with_context(|_| ErrorKind::HttpServer)?;
+        listener
+            .set_nonblocking(true)
+            .with_context(|_| ErrorKind::HttpServer)?;
+        let thread = Builder::new("humthreads-http")
+            .full_name(format!("humthreads HTTP server on {}", address))
+            .spawn(move |scope| serve(listener, scope))?;
+        Ok(HttpServer {
+            address,
+            thread: Some(thread),
+        })
+    }
+
+    /// Address the server is listening on.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// Stop accepting requests and wait for the server thread to exit.
+    ///
+    /// Requests already being handled are allowed to complete in the background.
+    pub fn stop(mut self) -> Result<()> {
+        self.shutdown()
+    }
+
+    fn shutdown(&mut self) -> Result<()> {
+        match self.thread.take() {
+            Some(thread) => {
+                thread.request_shutdown();
+                thread.join()
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for HttpServer {
+    fn drop(&mut self) {
+        // Errors can't be reported when the server is dropped.
+        let _ = self.shutdown();
+    }
+}
+
+/// Accept connections until shutdown is requested and handle them in their own thread.
+fn serve(listener: TcpListener, scope: ThreadScope) {
+    let connections = Arc::new(AtomicUsize::new(0));
+    scope.static_activity("waiting for connections");
+    while !scope.should_shutdown() {
+        match listener.accept() {
+            Ok((stream, peer)) => {
+                // Drop connections over the limit by dropping the stream.
+                let slot = match ConnectionSlot::acquire(&connections, MAX_CONNECTIONS) {
+                    Some(slot) => slot,
+                    None => continue,
+                };
+                // The slot is released even if the thread fails to spawn.
+                let _ = Builder::new("humthreads-http-conn")
+                    .full_name(format!("humthreads HTTP connection from {}", peer))
+                    .spawn(move |scope| {
+                        let _slot = slot;
+                        let _activity = scope.scoped_activity("handling HTTP request");
+                        // Errors are specific to a client so ignore them and move on.
+                        let _ = handle(stream);
+                    });
+            }
+            Err(_) => ::std::thread::sleep(ACCEPT_INTERVAL),
+        }
+    }
+}
+
+/// Read a request from the stream and write the response back.
+fn handle(mut stream: TcpStream) -> ::std::io::Result<()> {
+    let deadline = Instant::now() + REQUEST_TIMEOUT;
+    stream.set_nonblocking(false)?;
+    stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+
+    // Read the request line and headers, any request body is ignored.
+    let mut request = Vec::new();
+    let mut buffer = [0; 1024];
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+        if request.len() > MAX_REQUEST_SIZE {
+            return respond(&mut stream, "431 Request Header Fields Too Large", "", "");
+        }
+        // Bound the total time to read the request and not just the time between reads.
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining == Duration::ZERO {
+            return Err(IoErrorKind::TimedOut.into());
+        }
+        stream.set_read_timeout(Some(remaining))?;
+        let read = stream.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        request.extend_from_slice(&buffer[..read]);
+    }
+    let request = String::from_utf8_lossy(&request);
+    let mut line = request.lines().next().unwrap_or("").split(' ');
+    let method = line.next().unwrap_or("");
+    let path = line.next().unwrap_or("");
+    let (path, query) = path.split_once('?').unwrap_or((path, ""));
+    let cpu_time = query.split('&').any(|param| param == "cpu_time=true");
+
+    if method != "GET" {
+        return respond(&mut stream, "405 Method Not Allowed", "", "");
+    }
+    match path {
+        "/metrics" => respond(
+            &mut stream,
+            "200 OK",
+            "text/plain; version=0.0.4; charset=utf-8",
+            &prometheus_metrics(),
+        ),
+        "/threads" => {
+            let threads = ThreadQuery::new().with_cpu_time(cpu_time).run();
+            match serde_json::to_string(&threads) {
+                Ok(body) => respond(&mut stream, "200 OK", "application/json", &body),
+                Err(_) => respond(&mut stream, "500 Internal Server Error", "", ""),
+            }
+        }
+        "/threads.txt" => respond(
+            &mut stream,
+            "200 OK",
+            "text/plain; charset=utf-8",
+            &text_table(&registered_threads()),
+        ),
+        _ => respond(&mut stream, "404 Not Found", "", ""),
+    }
+}
+
+/// Write a complete response and close the connection.
+fn respond(
+    stream: &mut TcpStream,
+    status: &str,
+    content_type: &str,
+    body: &str,
+) -> ::std::io::Result<()> {
+    let mut response = format!("HTTP/1.1 {}\r\n", status);
+    if !content_type.is_empty() {
+        response.push_str(&format!("Content-Type: {}\r\n", content_type));
+    }
+    response.push_str(&format!(
+        "Content-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    ));
+    response.push_str(body);
+    stream.write_all(response.as_bytes())?;
+    stream.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::io::Write;
+    use std::net::SocketAddr;
+    use std::net::TcpStream;
+    use std::time::Duration;
+    use std::time::Instant;
+
+    use super::super::ThreadStatus;
+    use super::HttpServer;
+
+    fn get(address: SocketAddr, request: &str) -> String {
+        let mut stream = TcpStream::connect(address).expect("to connect to the server");
+        stream
+            .write_all(request.as_bytes())
+            .expect("to send the request");
+        let mut response = String::new();
+        stream
+            .read_to_string(&mut response)
+            .expect("to read the response");
+        response
+    }
+
+    #[test]
+    fn serve_endpoints() {
+        let server = HttpServer::start("127.0.0.1:0").expect("to start the server");
+        let address = server.local_addr();
+        let json = get(address, "GET /threads HTTP/1.1\r\nHost: test\r\n\r\n");
+        let cpu = get(address, "GET /threads?cpu_time=true HTTP/1.1\r\n\r\n");
+        let table = get(address, "GET /threads.txt HTTP/1.1\r\n\r\n");
+        let metrics = get(address, "GET /metrics?format=text HTTP/1.1\r\n\r\n");
+        let missing = get(address, "GET /missing HTTP/1.1\r\n\r\n");
+        let post = get(address, "POST /threads HTTP/1.1\r\n\r\n");
+        server.stop().expect("the server to stop");
+
+        assert!(json.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(json.contains("Content-Type: application/json\r\n"));
+        let body = json.split("\r\n\r\n").nth(1).expect("response has a body");
+        let threads: Vec<ThreadStatus> = serde_json::from_str(body).expect("valid JSON body");
+        assert!(threads
+            .iter()
+            .any(|thread| thread.short_name == "humthreads-http"
+                && thread.activity.as_deref() == Some("waiting for connections")));
+        assert!(threads
+            .iter()
+            .any(|thread| thread.short_name == "humthreads-http-conn"
+                && thread.activity.as_deref() == Some("handling HTTP request")));
+        assert!(threads.iter().all(|thread| thread.cpu_time.is_none()));
+        let body = cpu.split("\r\n\r\n").nth(1).expect("response has a body");
+        let threads: Vec<ThreadStatus> = serde_json::from_str(body).expect("valid JSON body");
+        assert!(threads
+            .iter()
+            .all(|thread| thread.cpu_time.is_some() == cfg!(target_os = "linux")));
+        assert!(table.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(table.contains("humthreads HTTP server on 127.0.0.1:"));
+        assert!(metrics.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(metrics.contains("# TYPE humthreads_threads gauge\n"));
+        assert!(missing.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        assert!(post.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+    }
+
+    #[test]
+    fn slow_clients_do_not_block_requests() {
+        let server = HttpServer::start("127.0.0.1:0").expect("to start the server");
+        let address = server.local_addr();
+        let mut slow = TcpStream::connect(address).expect("to connect to the server");
+        slow.write_all(b"GET /threads HTTP/1.1\r\n")
+            .expect("to send part of the request");
+        let start = Instant::now();
+        let response = get(address, "GET /threads.txt HTTP/1.1\r\n\r\n");
+        let elapsed = start.elapsed();
+        drop(slow);
+        server.stop().expect("the server to stop");
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(elapsed < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn stop_on_drop() {
+        let server = HttpServer::start("127.0.0.1:0").expect("to start the server");
+        let address = server.local_addr();
+        drop(server);
+        assert!(TcpStream::connect(address).is_err());
+    }
+}
@@ -0,0 +1,385 @@
+use std::any::Any;
+use std::panic::catch_unwind;
+use std::panic::AssertUnwindSafe;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use crossbeam_channel::Receiver;
+use crossbeam_channel::Sender;
+
+use super::ActivityFields;
+use super::ThreadStatus;
+
+/// Default time budget for inspect callbacks.
+pub(crate) const DEFAULT_INSPECT_BUDGET: Duration = Duration::from_millis(10);
+
+/// Extra time allowed for workers to start or be scheduled before callbacks are abandoned.
+const HELPER_START_GRACE: Duration = Duration::from_millis(100);
+
+/// Name of the worker threads invoking callbacks, which tags their panic messages.
+const INSPECT_THREAD_NAME: &str = "humthreads-inspect";
+
+/// Maximum number of idle inspector workers kept for later snapshots.
+const MAX_IDLE_WORKERS: usize = 4;
+
+lazy_static::lazy_static! {
+    static ref IDLE_WORKERS: Mutex<Vec<InspectWorker>> = Mutex::new(Vec::new());
+}
+
+/// Function returning fields to include in a thread's status.
+type InspectFn = Box<dyn Fn() -> ActivityFields + Send + Sync>;
+
+/// Internal tracking of callbacks registered by a thread to report its status on demand.
+///
+/// Callbacks are invoked on a persistent inspector worker on behalf of the thread requesting
+/// a snapshot, after the registry is unlocked, so a blocked callback can't block snapshots.
+/// A callback that panics or exceeds its time budget is permanently disabled and the problem
+/// is reported in the status of the thread that registered it.
+#[derive(Default)]
+pub(crate) struct ThreadInspectors {
+    callbacks: Mutex<Vec<Arc<InspectCallback>>>,
+}
+
+impl ThreadInspectors {
+    /// Register a new callback.
+    pub(crate) fn add(&self, budget: Duration, callback: InspectFn) {
+        let callback = InspectCallback {
+            budget,
+            callback,
+            error: Mutex::new(None),
+            disabled: AtomicBool::new(false),
+        };
+        self.callbacks
+            .lock()
+            .expect("ThreadInspectors::callbacks lock poisoned")
+            .push(Arc::new(callback));
+    }
+
+    /// Invoke all callbacks and add the fields they return to the given status.
+    pub(crate) fn inspect(&self, status: &mut ThreadStatus) {
+        // Release the lock before invoking callbacks so they can't block registrations.
+        let callbacks = self
+            .callbacks
+            .lock()
+            .expect("ThreadInspectors::callbacks lock poisoned")
+            .clone();
+        let mut worker = None;
+        for callback in callbacks {
+            callback.invoke(status, &mut worker);
+        }
+        if let Some(worker) = worker {
+            worker.release();
+        }
+    }
+}
+
+struct InspectCallback {
+    budget: Duration,
+    callback: InspectFn,
+    disabled: AtomicBool,
+    error: Mutex<Option<String>>,
+}
+
+impl InspectCallback {
+    fn disable(&self, error: String) {
+        self.disabled.store(true, Ordering::Relaxed);
+        *self
+            .error
+            .lock()
+            .expect("InspectCallback::error lock poisoned") = Some(error);
+    }
+
+    /// Invoke the callback on an inspector worker and wait for it until its budget expires.
+    ///
+    /// Workers running callbacks that don't return in time are abandoned: they exit once the
+    /// callback returns and the callback is never invoked again.
+    fn invoke(self: &Arc<Self>, status: &mut ThreadStatus, worker: &mut Option<InspectWorker>) {
+        if self.disabled.load(Ordering::Relaxed) {
+            let error = self
+                .error
+                .lock()
+                .expect("InspectCallback::error lock poisoned")
+                .clone();
+            status.inspect_errors.extend(error);
+            return;
+        }
+        let current = match worker.take().map_or_else(InspectWorker::take, Ok) {
+            Ok(current) => current,
+            Err(error) => {
+                let error = format!("unable to start inspect callback: {}", error);
+                status.inspect_errors.push(error);
+                return;
+            }
+        };
+        let result = current.run(self);
+        let (fields, elapsed) = match result {
+            Some(result) => {
+                *worker = Some(current);
+                result
+            }
+            None => {
+                let error = format!(
+                    "inspect callback did not return within its budget of {:?} and was abandoned",
+                    self.budget
+                );
+                self.disable(error.clone());
+                status.inspect_errors.push(error);
+                return;
+            }
+        };
+        match fields {
+            Err(error) => {
+                let error = format!(
+                    "inspect callback panicked: {}",
+                    panic_message(error.as_ref())
+                );
+                self.disable(error.clone());
+                status.inspect_errors.push(error);
+            }
+            Ok(fields) => {
+                // Late results are still valid but the callback is not invoked again.
+                if elapsed > self.budget {
+                    let error = format!(
+                        "inspect callback took {:?}, exceeding its budget of {:?}",
+                        elapsed, self.budget
+                    );
+                    self.disable(error.clone());
+                    status.inspect_errors.push(error);
+                }
+                status.inspected.extend(fields);
+            }
+        }
+    }
+}
+
+/// Result of a callback invoked by an inspector worker and the time it took.
+type InspectResult = (::std::thread::Result<ActivityFields>, Duration);
+
+/// Persistent helper thread invoking callbacks on behalf of threads taking snapshots.
+///
+/// Idle workers are kept in a pool and reused by later snapshots.
+/// Each snapshot uses one worker at a time so callbacks are invoked one after the other
+/// and a worker is only replaced when a callback overruns its budget.
+struct InspectWorker {
+    jobs: Sender<Arc<InspectCallback>>,
+    results: Receiver<InspectResult>,
+}
+
+impl InspectWorker {
+    /// Take an idle worker from the pool or start a new one.
+    fn take() -> ::std::io::Result<InspectWorker> {
+        let idle = IDLE_WORKERS
+            .lock()
+            .expect("IDLE_WORKERS lock poisoned")
+            .pop();
+        match idle {
+            Some(worker) => Ok(worker),
+            None => InspectWorker::start(),
+        }
+    }
+
+    fn start() -> ::std::io::Result<InspectWorker> {
+        let (jobs, receive_jobs) = ::crossbeam_channel::unbounded::<Arc<InspectCallback>>();
+        let (send_results, results) = ::crossbeam_channel::bounded(1);
+        ::std::thread::Builder::new()
+            .name(INSPECT_THREAD_NAME.into())
+            .spawn(move || {
+                // Exit when the worker is dropped, after any callback in progress returns.
+                for callback in receive_jobs {
+                    let start = Instant::now();
+                    let fields = catch_unwind(AssertUnwindSafe(|| (callback.callback)()));
+                    if send_results.send((fields, start.elapsed())).is_err() {
+                        break;
+                    }
+                }
+            })?;
+        Ok(InspectWorker { jobs, results })
+    }
+
+    /// Return the worker to the pool, unless enough workers are already idle.
+    fn release(self) {
+        let mut idle = IDLE_WORKERS.lock().expect("IDLE_WORKERS lock poisoned");
+        if idle.len() < MAX_IDLE_WORKERS {
+            idle.push(self);
+        }
+    }
+
+    /// Invoke the callback, returning `None` if it did not return within its budget.
+    fn run(&self, callback: &Arc<InspectCallback>) -> Option<InspectResult> {
+        // Workers catch panics so they only stop when dropped.
+        self.jobs.send(Arc::clone(callback)).ok()?;
+        self.results
+            .recv_timeout(callback.budget + HELPER_START_GRACE)
+            .ok()
+    }
+}
+
+/// Extract the message from a panic payload, if it is a string.
+pub(crate) fn panic_message(error: &(dyn Any + Send)) -> &str {
+    if let Some(message) = error.downcast_ref::<&str>() {
+        return message;
+    }
+    if let Some(message) = error.downcast_ref::<String>() {
+        return message;
+    }
+    "unknown panic payload"
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Arc;
+    use std::sync::Mutex;
+    use std::time::Duration;
+    use std::time::Instant;
+
+    use super::super::registered_threads;
+    use super::super::status::RegisteredStatus;
+    use super::super::status::StatusConfig;
+    use super::super::ActivityFields;
+    use super::super::ActivityValue;
+    use super::super::Builder;
+    use super::super::ThreadStatus;
+    use super::ThreadInspectors;
+
+    #[test]
+    fn callbacks_add_fields() {
+        let thread = Builder::new("callbacks_add_fields")
+            .spawn(|scope| {
+                scope.on_inspect(|| {
+                    let mut fields = ActivityFields::new();
+                    fields.insert("buffer length".into(), 42.into());
+                    fields
+                });
+                while !scope.should_shutdown() {
+                    ::std::thread::sleep(Duration::from_millis(10));
+                }
+            })
+            .expect("to spawn test thread");
+
+        // Give it a chance to register and collect list.
+        ::std::thread::sleep(Duration::from_millis(20));
+        let threads = registered_threads();
+        thread.request_shutdown();
+        thread.join().expect("the thread to stop");
+
+        let thread = threads
+            .into_iter()
+            .find(|t| t.name == "callbacks_add_fields")
+            .expect("test thread not found");
+        assert_eq!(
+            Some(&ActivityValue::Int(42)),
+            thread.inspected.get("buffer length")
+        );
+        assert!(thread.inspect_errors.is_empty());
+    }
+
+    #[test]
+    fn blocked_callbacks_are_abandoned() {
+        let (release, blocked) = ::crossbeam_channel::bounded::<()>(0);
+        let thread = Builder::new("blocked_callbacks_are_abandoned")
+            .spawn(move |scope| {
+                scope.on_inspect_with_budget(Duration::from_millis(1), move || {
+                    let _ = blocked.recv();
+                    ActivityFields::new()
+                });
+                while !scope.should_shutdown() {
+                    ::std::thread::sleep(Duration::from_millis(10));
+                }
+            })
+            .expect("to spawn test thread");
+
+        // Give it a chance to register and collect list twice.
+        ::std::thread::sleep(Duration::from_millis(20));
+        let start = Instant::now();
+        let first = registered_threads();
+        let second = registered_threads();
+        let elapsed = start.elapsed();
+        thread.request_shutdown();
+        thread.join().expect("the thread to stop");
+        drop(release);
+
+        assert!(elapsed < Duration::from_secs(1));
+        for threads in [first, second] {
+            let thread = threads
+                .into_iter()
+                .find(|t| t.name == "blocked_callbacks_are_abandoned")
+                .expect("test thread not found");
+            assert_eq!(1, thread.inspect_errors.len());
+            assert!(thread.inspect_errors[0].contains("was abandoned"));
+        }
+    }
+
+    #[test]
+    fn callbacks_share_a_worker() {
+        let status = RegisteredStatus::new(
+            1,
+            "callbacks_share_a_worker".into(),
+            "callbacks_share_a_worker".into(),
+            None,
+            None,
+            StatusConfig::default(),
+            Arc::new(AtomicBool::new(false)),
+        );
+        let workers = Arc::new(Mutex::new(HashSet::new()));
+        let inspectors = ThreadInspectors::default();
+        for _ in 0..3 {
+            let workers = Arc::clone(&workers);
+            inspectors.add(
+                Duration::from_secs(1),
+                Box::new(move || {
+                    workers
+                        .lock()
+                        .unwrap()
+                        .insert(::std::thread::current().id());
+                    ActivityFields::new()
+                }),
+            );
+        }
+        let mut snapshot = ThreadStatus::from(&status);
+        inspectors.inspect(&mut snapshot);
+
+        assert!(snapshot.inspect_errors.is_empty());
+        let workers = workers.lock().unwrap();
+        assert_eq!(1, workers.len());
+        assert!(!workers.contains(&::std::thread::current().id()));
+    }
+
+    #[test]
+    fn callbacks_are_isolated() {
+        let thread = Builder::new("callbacks_are_isolated")
+            .spawn(|scope| {
+                scope.on_inspect(|| panic!("this panic is expected"));
+                scope.on_inspect_with_budget(Duration::from_millis(1), || {
+                    ::std::thread::sleep(Duration::from_millis(5));
+                    ActivityFields::new()
+                });
+                while !scope.should_shutdown() {
+                    ::std::thread::sleep(Duration::from_millis(10));
+                }
+            })
+            .expect("to spawn test thread");
+
+        // Give it a chance to register and collect list twice.
+        ::std::thread::sleep(Duration::from_millis(20));
+        let first = registered_threads();
+        let second = registered_threads();
+        thread.request_shutdown();
+        thread.join().expect("the thread to stop");
+
+        for threads in [first, second] {
+            let thread = threads
+                .into_iter()
+                .find(|t| t.name == "callbacks_are_isolated")
+                .expect("test thread not found");
+            assert_eq!(2, thread.inspect_errors.len());
+            assert!(thread.inspect_errors[0].contains("this panic is expected"));
+            assert!(thread.inspect_errors[1].contains("exceeding its budget"));
+        }
+    }
+}
@@ -0,0 +1,193 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+/// Maximum number of distinct activities tracked for each thread.
+const MAX_ACTIVITIES: usize = 64;
+
+/// Name used to track activities once the `MAX_ACTIVITIES` limit is reached.
+const OTHER_ACTIVITIES: &str = "(other)";
+
+/// Number of bits used to divide each power of two into sub-buckets.
+///
+/// Four bits means 16 linear sub-buckets per power of two and a relative error below 6.25%.
+const SUB_BUCKET_BITS: u32 = 4;
+const SUB_BUCKETS: u64 = 1 << SUB_BUCKET_BITS;
+
+/// Public view of the duration of scoped activities with the same name.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Default, Serialize, Deserialize)]
+pub struct LatencySummary {
+    /// Number of completed scoped activities.
+    pub count: u64,
+
+    /// Longest duration recorded.
+    pub max: Duration,
+
+    /// Median duration.
+    pub p50: Duration,
+
+    /// 99th percentile duration.
+    pub p99: Duration,
+}
+
+/// Internal tracking of scoped activity durations for a thread.
+///
+/// Memory is bounded by tracking at most `MAX_ACTIVITIES` activity names,
+/// additional activities are tracked together, and by using fixed precision histograms.
+/// Activity names should therefore describe kinds of work, with details attached as fields.
+#[derive(Default)]
+pub(crate) struct ThreadLatencies {
+    histograms: Mutex<BTreeMap<String, LatencyHistogram>>,
+}
+
+impl ThreadLatencies {
+    /// Record the duration of a completed scoped activity.
+    pub(crate) fn record(&self, activity: String, duration: Duration) {
+        let mut histograms = self
+            .histograms
+            .lock()
+            .expect("ThreadLatencies::histograms lock poisoned");
+        let activity = if histograms.len() >= MAX_ACTIVITIES && !histograms.contains_key(&activity)
+        {
+            OTHER_ACTIVITIES.to_string()
+        } else {
+            activity
+        };
+        histograms.entry(activity).or_default().record(duration);
+    }
+
+    /// Summarise recorded durations by activity.
+    pub(crate) fn summaries(&self) -> BTreeMap<String, LatencySummary> {
+        self.histograms
+            .lock()
+            .expect("ThreadLatencies::histograms lock poisoned")
+            .iter()
+            .map(|(activity, histogram)| (activity.clone(), histogram.summary()))
+            .collect()
+    }
+}
+
+/// Log-linear histogram of durations in nanoseconds, similar to HDR histograms.
+///
+/// Values are grouped into buckets by power of two, each split into linear sub-buckets.
+/// Only buckets with recorded values are stored.
+#[derive(Default)]
+struct LatencyHistogram {
+    buckets: BTreeMap<u16, u64>,
+    count: u64,
+    max: u64,
+}
+
+impl LatencyHistogram {
+    /// Return the value at the given quantile (between 0 and 1).
+    fn quantile(&self, quantile: f64) -> u64 {
+        let target = ((self.count as f64 * quantile).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (bucket, count) in &self.buckets {
+            seen += count;
+            if seen >= target {
+                return bucket_value(*bucket).min(self.max);
+            }
+        }
+        self.max
+    }
+
+    fn record(&mut self, duration: Duration) {
+        let value = u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX);
+        *self.buckets.entry(bucket_index(value)).or_default() += 1;
+        self.count += 1;
+        self.max = self.max.max(value);
+    }
+
+    fn summary(&self) -> LatencySummary {
+        LatencySummary {
+            count: self.count,
+            max: Duration::from_nanos(self.max),
+            p50: Duration::from_nanos(self.quantile(0.5)),
+            p99: Duration::from_nanos(self.quantile(0.99)),
+        }
+    }
+}
+
+/// Find the bucket a value belongs to.
+fn bucket_index(value: u64) -> u16 {
+    if value < SUB_BUCKETS {
+        return value as u16;
+    }
+    let exponent = 63 - value.leading_zeros();
+    let shift = exponent - SUB_BUCKET_BITS;
+    let sub_bucket = (value >> shift) - SUB_BUCKETS;
+    (SUB_BUCKETS + u64::from(shift) * SUB_BUCKETS + sub_bucket) as u16
+}
+
+/// Representative value of a bucket: the middle of the range of values it holds.
+fn bucket_value(index: u16) -> u64 {
+    let index = u64::from(index);
+    if index < SUB_BUCKETS {
+        return index;
+    }
+    let shift = (index - SUB_BUCKETS) / SUB_BUCKETS;
+    let sub_bucket = (index - SUB_BUCKETS) % SUB_BUCKETS;
+    let low = (SUB_BUCKETS + sub_bucket) << shift;
+    low + ((1 << shift) >> 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::bucket_index;
+    use super::bucket_value;
+    use super::LatencyHistogram;
+    use super::ThreadLatencies;
+    use super::MAX_ACTIVITIES;
+
+    #[test]
+    fn buckets_precision() {
+        for value in [
+            0,
+            1,
+            15,
+            16,
+            17,
+            100,
+            1_000,
+            123_456,
+            10_000_000_000,
+            u64::MAX,
+        ] {
+            let approx = bucket_value(bucket_index(value));
+            let error = (approx as f64 - value as f64).abs() / (value as f64).max(1.0);
+            assert!(error < 0.0625, "value {} approximated as {}", value, approx);
+        }
+    }
+
+    #[test]
+    fn quantiles() {
+        let mut histogram = LatencyHistogram::default();
+        for millis in 1..=100 {
+            histogram.record(Duration::from_millis(millis));
+        }
+        let summary = histogram.summary();
+        assert_eq!(100, summary.count);
+        assert_eq!(Duration::from_millis(100), summary.max);
+        let p50 = summary.p50.as_secs_f64() * 1000.0;
+        let p99 = summary.p99.as_secs_f64() * 1000.0;
+        assert!((p50 - 50.0).abs() < 50.0 * 0.0625, "p50 is {}", p50);
+        assert!((p99 - 99.0).abs() < 99.0 * 0.0625, "p99 is {}", p99);
+    }
+
+    #[test]
+    fn bounded_activities() {
+        let latencies = ThreadLatencies::default();
+        for activity in 0..(MAX_ACTIVITIES + 10) {
+            latencies.record(format!("activity {}", activity), Duration::from_millis(1));
+        }
+        let summaries = latencies.summaries();
+        assert_eq!(MAX_ACTIVITIES + 1, summaries.len());
+        assert_eq!(10, summaries["(other)"].count);
+    }
+}
@@ -109,6 +109,62 @@
 //!# }
 //! ```
 //!
+//! The [`ThreadScope`] of registered threads is also available without passing it around
+//! through the [`current`] function and helpers such as [`activity`] and [`should_shutdown`].
+//! These helpers do nothing when called from threads that are not registered.
+//!
+//! ```
+//! use humthreads::Builder;
+//!
+//!# fn main() {
+//! fn process(task: usize) {
+//!     let _activity = humthreads::scoped_activity(format!("processing task {}", task));
+//! }
+//!
+//! let thread = Builder::new("os-thread-name")
+//!     .spawn(|_| {
+//!         for task in 0..10 {
+//!             if humthreads::should_shutdown() {
+//!                 break;
+//!             }
+//!             process(task);
+//!         }
+//!     })
+//!     .expect("failed to spawn thread");
+//! thread.join().expect("background thread paniced");
+//!# }
+//! ```
+//!
+//! ### Grouping threads
+//! Threads that work together can be spawned into a [`ThreadGroup`] to inspect and
+//! control them as a whole:
+//!
+//! ```
+//! use std::thread::sleep;
+//! use std::time::Duration;
+//!
+//! use humthreads::Builder;
+//! use humthreads::ThreadGroup;
+//!
+//!# fn main() {
+//! let group = ThreadGroup::new("workers");
+//! for idx in 0..3 {
+//!     Builder::new(format!("worker-{}", idx))
+//!         .group(&group)
+//!         .spawn(|scope| {
+//!             while !scope.should_shutdown() {
+//!                 sleep(Duration::from_millis(10));
+//!             }
+//!         })
+//!         .expect("failed to spawn worker");
+//! }
+//!
+//! group.request_shutdown();
+//! group.join_timeout(Duration::from_secs(1)).expect("workers did not stop in time");
+//! assert_eq!(3, group.status().exited);
+//!# }
+//! ```
+//!
 //! ### Waiting for threads with Select
 //! ```
 //! use std::thread::sleep;
@@ -144,6 +200,11 @@
 //! [`Thread::join_timeout`] to join with the thread.
 //!
 //! [`Builder`]: struct.Builder.html
+//! [`ThreadGroup`]: struct.ThreadGroup.html
+//! [`ThreadScope`]: struct.ThreadScope.html
+//! [`activity`]: fn.activity.html
+//! [`current`]: fn.current.html
+//! [`should_shutdown`]: fn.should_shutdown.html
 //! [`Thread::join`]: struct.Thread.html#method.join
 //! [`Thread::join_timeout`]: struct.Thread.html#method.join_timeout
 //! [`std::thread`]: https://doc.rust-lang.org/stable/std/thread/index.html
@@ -151,21 +212,126 @@
 //! [`Select::ready`]: https://docs.rs/crossbeam-channel/*/crossbeam_channel/struct.Select.html
 #![doc(html_root_url = "https://docs.rs/humthreads/0.2.1")]
 
+mod activity;
 mod builder;
+#[cfg(any(feature = "with_http", all(unix, feature = "with_socket")))]
+mod connections;
+#[cfg(feature = "with_crash_report")]
+mod crash;
+mod current;
+#[cfg(all(target_os = "linux", feature = "with_signal_dump"))]
+mod dump;
 mod error;
+mod group;
 mod handles;
+mod history;
+#[cfg(feature = "with_http")]
+mod http;
+mod inspect;
+mod latency;
+mod lifecycle;
+#[cfg(feature = "with_log")]
+mod logger;
+mod metrics;
+mod os;
+mod pool;
+mod progress;
+#[cfg(feature = "with_prometheus")]
+mod prometheus;
+mod query;
 mod registry;
+mod sampler;
+#[cfg(all(unix, feature = "with_socket"))]
+mod socket;
 mod status;
+mod table;
 #[cfg(feature = "with_test_support")]
 pub mod test_support;
+#[cfg(feature = "with_trace")]
+mod trace;
+#[cfg(feature = "with_tracing")]
+mod tracing_layer;
+mod utilization;
 
+pub use self::activity::ActivityFields;
+pub use self::activity::ActivityFrame;
+pub use self::activity::ActivityName;
+pub use self::activity::ActivityReport;
+pub use self::activity::ActivityValue;
+pub use self::builder::register_current_thread;
 pub use self::builder::Builder;
+#[cfg(feature = "with_crash_report")]
+pub use self::crash::install_crash_reports;
+#[cfg(feature = "with_crash_report")]
+pub use self::crash::CrashReport;
+pub use self::current::activity;
+pub use self::current::current;
+pub use self::current::scoped_activity;
+pub use self::current::should_shutdown;
+#[cfg(all(target_os = "linux", feature = "with_signal_dump"))]
+pub use self::dump::thread_dump;
+#[cfg(all(target_os = "linux", feature = "with_signal_dump"))]
+pub use self::dump::DumpOnSignal;
+#[cfg(all(target_os = "linux", feature = "with_signal_dump"))]
+pub use self::dump::DumpTarget;
 pub use self::error::Error;
 pub use self::error::ErrorKind;
 pub use self::error::Result;
+pub use self::group::ThreadGroup;
+pub use self::group::ThreadGroupStatus;
 pub use self::handles::MapThread;
+pub use self::handles::RegisteredThreadGuard;
 pub use self::handles::Thread;
 pub use self::handles::ThreadScope;
 pub use self::handles::ThreadScopeActivityGuard;
+pub use self::history::ActivityRecord;
+#[cfg(feature = "with_http")]
+pub use self::http::HttpServer;
+pub use self::latency::LatencySummary;
+#[cfg(feature = "with_log")]
+pub use self::logger::ThreadLogger;
+pub use self::metrics::Counter;
+pub use self::metrics::Gauge;
+pub use self::pool::PoolSpawner;
+pub use self::progress::ThreadProgress;
+#[cfg(feature = "with_prometheus")]
+pub use self::prometheus::prometheus_metrics;
+#[cfg(feature = "with_prometheus")]
+pub use self::prometheus::render_prometheus;
+pub use self::query::ThreadQuery;
+pub use self::registry::panicked_threads;
 pub use self::registry::registered_threads;
+pub use self::registry::registry_counters;
+pub use self::registry::request_thread_shutdown;
+pub use self::registry::thread_history;
+pub use self::registry::thread_tree;
+pub use self::registry::RegistryCounters;
+pub use self::sampler::ActivitySampler;
+#[cfg(all(unix, feature = "with_socket"))]
+pub use self::socket::SocketClient;
+#[cfg(all(unix, feature = "with_socket"))]
+pub use self::socket::SocketCommand;
+#[cfg(all(unix, feature = "with_socket"))]
+pub use self::socket::SocketReply;
+#[cfg(all(unix, feature = "with_socket"))]
+pub use self::socket::SocketRequest;
+#[cfg(all(unix, feature = "with_socket"))]
+pub use self::socket::SocketResponse;
+#[cfg(all(unix, feature = "with_socket"))]
+pub use self::socket::SocketServer;
+#[cfg(all(unix, feature = "with_socket"))]
+pub use self::socket::SOCKET_PROTOCOL_VERSION;
 pub use self::status::ThreadStatus;
+pub use self::status::ThreadTreeNode;
+pub use self::table::align_columns;
+pub use self::table::format_age;
+pub use self::table::text_table;
+#[cfg(feature = "with_trace")]
+pub use self::trace::Trace;
+#[cfg(feature = "with_trace")]
+pub use self::trace::TraceEvent;
+#[cfg(feature = "with_trace")]
+pub use self::trace::TraceRecorder;
+#[cfg(feature = "with_tracing")]
+pub use self::tracing_layer::ActivityLayer;
+pub use self::utilization::ThreadUtilization;
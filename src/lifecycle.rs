@@ -0,0 +1,62 @@
+//! Notifications of registered threads lifecycle transitions.
+//!
+//! With the `with_tracing` feature transitions are emitted as `tracing` events
+//! with the `humthreads` target, otherwise these functions do nothing.
+
+#[cfg(feature = "with_tracing")]
+use tracing::Level;
+
+/// A thread was registered.
+#[cfg(feature = "with_tracing")]
+pub(crate) fn spawned(id: u64, name: &str) {
+    tracing::event!(
+        target: "humthreads",
+        Level::INFO,
+        thread.id = id,
+        thread.name = name,
+        "thread spawned"
+    );
+}
+
+/// A thread was asked to terminate.
+#[cfg(feature = "with_tracing")]
+pub(crate) fn shutdown_requested(id: u64, name: &str) {
+    tracing::event!(
+        target: "humthreads",
+        Level::INFO,
+        thread.id = id,
+        thread.name = name,
+        "thread shutdown requested"
+    );
+}
+
+/// A thread exited, either normally or because of a panic.
+#[cfg(feature = "with_tracing")]
+pub(crate) fn exited(id: u64, name: &str, panicked: bool) {
+    if panicked {
+        tracing::event!(
+            target: "humthreads",
+            Level::ERROR,
+            thread.id = id,
+            thread.name = name,
+            "thread panicked"
+        );
+    } else {
+        tracing::event!(
+            target: "humthreads",
+            Level::INFO,
+            thread.id = id,
+            thread.name = name,
+            "thread exited"
+        );
+    }
+}
+
+#[cfg(not(feature = "with_tracing"))]
+pub(crate) fn spawned(_id: u64, _name: &str) {}
+
+#[cfg(not(feature = "with_tracing"))]
+pub(crate) fn shutdown_requested(_id: u64, _name: &str) {}
+
+#[cfg(not(feature = "with_tracing"))]
+pub(crate) fn exited(_id: u64, _name: &str, _panicked: bool) {}
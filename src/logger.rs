@@ -0,0 +1,292 @@
+use std::cell::Cell;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use log::Log;
+use log::Metadata;
+use log::Record;
+
+use super::current::with_current_identity;
+use super::ThreadScope;
+
+thread_local! {
+    /// Set while the innermost activity is described for a record of this thread.
+    static DESCRIBING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// `log::Log` wrapper attributing records to the registered thread that logged them.
+///
+/// Messages logged by registered threads are prefixed with the thread's full name,
+/// registry id, labels and innermost activity before being forwarded to the inner logger:
+///
+/// ```text
+/// [worker-3 id=1234 pool=ingest activity="decoding batch"] message
+/// ```
+///
+/// Records logged by threads that are not registered are forwarded unchanged.
+/// The inner logger decides which records are enabled.
+///
+/// The thread name and labels are cached by the thread when it is registered so logging
+/// does not lock the global registry and only the innermost activity is rendered.
+/// Records logged while that activity is described, for example by an
+/// `ActivityReport::describe` implementation, are prefixed without the activity.
+///
+/// The wrapper is installed in place of the inner logger, for example with
+/// `log::set_boxed_logger(Box::new(ThreadLogger::new(inner)))`.
+pub struct ThreadLogger<L: Log> {
+    inner: L,
+}
+
+impl<L: Log> ThreadLogger<L> {
+    pub fn new(inner: L) -> ThreadLogger<L> {
+        ThreadLogger { inner }
+    }
+
+    /// Access the wrapped logger.
+    pub fn inner(&self) -> &L {
+        &self.inner
+    }
+}
+
+impl<L: Log> Log for ThreadLogger<L> {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn flush(&self) {
+        self.inner.flush()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.inner.enabled(record.metadata()) {
+            return;
+        }
+        let prefix = match with_current_identity(|identity, scope| {
+            let activity = innermost_activity(scope);
+            thread_prefix(
+                identity.id,
+                &identity.name,
+                &identity.labels,
+                activity.as_deref(),
+            )
+        }) {
+            Some(prefix) => prefix,
+            None => return self.inner.log(record),
+        };
+        self.inner.log(
+            &Record::builder()
+                .args(format_args!("{} {}", prefix, record.args()))
+                .metadata(record.metadata().clone())
+                .module_path(record.module_path())
+                .file(record.file())
+                .line(record.line())
+                .build(),
+        );
+    }
+}
+
+/// Describe the innermost activity unless it is already being described on this thread.
+fn innermost_activity(scope: &ThreadScope) -> Option<String> {
+    let describing = DESCRIBING
+        .try_with(|describing| describing.replace(true))
+        .unwrap_or(true);
+    if describing {
+        return None;
+    }
+    let activity = scope.innermost_activity();
+    let _ = DESCRIBING.try_with(|describing| describing.set(false));
+    activity
+}
+
+/// Format the prefix identifying a registered thread.
+fn thread_prefix(
+    id: u64,
+    name: &str,
+    labels: &BTreeMap<String, String>,
+    activity: Option<&str>,
+) -> String {
+    let mut prefix = format!("[{} id={}", name, id);
+    for (key, value) in labels {
+        let _ = write!(prefix, " {}={}", key, quoted(value));
+    }
+    if let Some(activity) = activity {
+        let _ = write!(prefix, " activity={}", quoted(activity));
+    }
+    prefix.push(']');
+    prefix
+}
+
+/// Quote values that would otherwise be ambiguous in the prefix.
+fn quoted(value: &str) -> String {
+    if value.is_empty() || value.contains([' ', '"', '=', ']']) {
+        format!("{:?}", value)
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::sync::Mutex;
+
+    use log::Level;
+    use log::LevelFilter;
+    use log::Log;
+    use log::Metadata;
+    use log::Record;
+
+    use super::super::registry::current_thread_id;
+    use super::super::ActivityReport;
+    use super::super::Builder;
+    use super::thread_prefix;
+    use super::ThreadLogger;
+
+    /// Logger capturing the level and message of info or more severe records.
+    #[derive(Default)]
+    struct Capture(Mutex<Vec<(Level, String)>>);
+
+    impl Log for Capture {
+        fn enabled(&self, metadata: &Metadata) -> bool {
+            metadata.level() <= Level::Info
+        }
+
+        fn flush(&self) {}
+
+        fn log(&self, record: &Record) {
+            self.0
+                .lock()
+                .unwrap()
+                .push((record.level(), record.args().to_string()));
+        }
+    }
+
+    /// Records captured by the logger installed with `log::set_boxed_logger`.
+    static INSTALLED: Mutex<Vec<(Level, String)>> = Mutex::new(Vec::new());
+
+    struct Installed;
+
+    impl Log for Installed {
+        fn enabled(&self, metadata: &Metadata) -> bool {
+            metadata.level() <= Level::Info
+        }
+
+        fn flush(&self) {}
+
+        fn log(&self, record: &Record) {
+            INSTALLED
+                .lock()
+                .unwrap()
+                .push((record.level(), record.args().to_string()));
+        }
+    }
+
+    /// Typed activity logging while it is described.
+    struct LoggingActivity;
+
+    impl ActivityReport for LoggingActivity {
+        fn describe(&self) -> String {
+            log::info!("describing activity");
+            "logging activity".into()
+        }
+    }
+
+    fn log(logger: &ThreadLogger<Capture>, level: Level, message: &str) {
+        logger.log(
+            &Record::builder()
+                .args(format_args!("{}", message))
+                .level(level)
+                .target("test")
+                .build(),
+        );
+    }
+
+    #[test]
+    fn prefix_format() {
+        let mut labels = BTreeMap::new();
+        labels.insert("pool".to_string(), "ingest".to_string());
+        labels.insert("shard".to_string(), "a b".to_string());
+        let prefix = thread_prefix(42, "worker-3", &labels, Some("decoding batch"));
+        assert_eq!(
+            "[worker-3 id=42 pool=ingest shard=\"a b\" activity=\"decoding batch\"]",
+            prefix
+        );
+    }
+
+    #[test]
+    fn registered_threads_are_attributed() {
+        let thread = Builder::new("log-worker")
+            .full_name("log worker with a long name")
+            .label("pool", "ingest")
+            .spawn(|scope| {
+                let logger = ThreadLogger::new(Capture::default());
+                log(&logger, Level::Info, "idle");
+                let _activity = scope.scoped_activity("decoding");
+                log(&logger, Level::Warn, "busy");
+                log(&logger, Level::Debug, "filtered");
+                let records = logger.inner().0.lock().unwrap().clone();
+                (current_thread_id(), records)
+            })
+            .expect("to spawn test thread");
+        let (id, records) = thread.join().expect("the thread to stop");
+        assert_eq!(
+            vec![
+                (
+                    Level::Info,
+                    format!("[log worker with a long name id={} pool=ingest] idle", id)
+                ),
+                (
+                    Level::Warn,
+                    format!(
+                        "[log worker with a long name id={} pool=ingest activity=decoding] busy",
+                        id
+                    )
+                ),
+            ],
+            records
+        );
+    }
+
+    #[test]
+    fn typed_activity_logging_from_describe() {
+        static LOGGER: ThreadLogger<Installed> = ThreadLogger { inner: Installed };
+        log::set_logger(&LOGGER).expect("to install the test logger");
+        log::set_max_level(LevelFilter::Info);
+        let thread = Builder::new("log-typed")
+            .spawn(|scope| {
+                scope.typed_activity(LoggingActivity);
+                log::info!("busy");
+                current_thread_id()
+            })
+            .expect("to spawn test thread");
+        let id = thread.join().expect("the thread to stop");
+        let records: Vec<(Level, String)> = INSTALLED
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, message)| message.starts_with("[log-typed "))
+            .cloned()
+            .collect();
+        assert_eq!(
+            vec![
+                (
+                    Level::Info,
+                    format!("[log-typed id={}] describing activity", id)
+                ),
+                (
+                    Level::Info,
+                    format!("[log-typed id={} activity=\"logging activity\"] busy", id)
+                ),
+            ],
+            records
+        );
+    }
+
+    #[test]
+    fn unregistered_threads_are_forwarded() {
+        let logger = ThreadLogger::new(Capture::default());
+        log(&logger, Level::Error, "unchanged");
+        let records = logger.inner().0.lock().unwrap().clone();
+        assert_eq!(vec![(Level::Error, "unchanged".to_string())], records);
+    }
+}
@@ -0,0 +1,138 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::AtomicI64;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+/// Monotonically increasing per-thread metric, such as messages processed.
+///
+/// Counters are created with [`ThreadScope::counter`] and are cheap to clone and update.
+///
+/// [`ThreadScope::counter`]: struct.ThreadScope.html#method.counter
+#[derive(Clone, Default)]
+pub struct Counter(Arc<AtomicU64>);
+
+impl Counter {
+    /// Increment the counter by `count`.
+    pub fn add(&self, count: u64) {
+        self.0.fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// Current value of the counter.
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Increment the counter by one.
+    pub fn inc(&self) {
+        self.add(1);
+    }
+}
+
+/// Per-thread metric that can go up and down, such as queue depth.
+///
+/// Gauges are created with [`ThreadScope::gauge`] and are cheap to clone and update.
+///
+/// [`ThreadScope::gauge`]: struct.ThreadScope.html#method.gauge
+#[derive(Clone, Default)]
+pub struct Gauge(Arc<AtomicI64>);
+
+impl Gauge {
+    /// Add `value` (which may be negative) to the gauge.
+    pub fn add(&self, value: i64) {
+        self.0.fetch_add(value, Ordering::Relaxed);
+    }
+
+    /// Decrement the gauge by one.
+    pub fn dec(&self) {
+        self.add(-1);
+    }
+
+    /// Current value of the gauge.
+    pub fn get(&self) -> i64 {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Increment the gauge by one.
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    /// Set the gauge to `value`.
+    pub fn set(&self, value: i64) {
+        self.0.store(value, Ordering::Relaxed);
+    }
+}
+
+/// Internal tracking of metrics published by a thread.
+///
+/// Metrics are looked up by name only when handles are created, updates
+/// go directly to the atomic value shared with the handles.
+#[derive(Default)]
+pub(crate) struct ThreadMetrics {
+    counters: Mutex<BTreeMap<String, Counter>>,
+    gauges: Mutex<BTreeMap<String, Gauge>>,
+}
+
+impl ThreadMetrics {
+    /// Return the named counter, creating it if needed.
+    pub(crate) fn counter(&self, name: String) -> Counter {
+        self.counters
+            .lock()
+            .expect("ThreadMetrics::counters lock poisoned")
+            .entry(name)
+            .or_default()
+            .clone()
+    }
+
+    /// Current value of all counters.
+    pub(crate) fn counters(&self) -> BTreeMap<String, u64> {
+        self.counters
+            .lock()
+            .expect("ThreadMetrics::counters lock poisoned")
+            .iter()
+            .map(|(name, counter)| (name.clone(), counter.get()))
+            .collect()
+    }
+
+    /// Return the named gauge, creating it if needed.
+    pub(crate) fn gauge(&self, name: String) -> Gauge {
+        self.gauges
+            .lock()
+            .expect("ThreadMetrics::gauges lock poisoned")
+            .entry(name)
+            .or_default()
+            .clone()
+    }
+
+    /// Current value of all gauges.
+    pub(crate) fn gauges(&self) -> BTreeMap<String, i64> {
+        self.gauges
+            .lock()
+            .expect("ThreadMetrics::gauges lock poisoned")
+            .iter()
+            .map(|(name, gauge)| (name.clone(), gauge.get()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ThreadMetrics;
+
+    #[test]
+    fn handles_share_values() {
+        let metrics = ThreadMetrics::default();
+        let processed = metrics.counter("processed".into());
+        processed.inc();
+        metrics.counter("processed".into()).add(2);
+        let depth = metrics.gauge("depth".into());
+        depth.set(10);
+        depth.dec();
+        metrics.gauge("depth".into()).add(-4);
+
+        assert_eq!(Some(&3), metrics.counters().get("processed"));
+        assert_eq!(Some(&5), metrics.gauges().get("depth"));
+    }
+}
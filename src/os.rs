@@ -0,0 +1,78 @@
+use std::time::Duration;
+
+/// Clock ticks per second used by `/proc/<pid>/task/<tid>/stat` times.
+///
+/// Linux exposes times to user space in `USER_HZ` units, which is 100 on all
+/// mainstream architectures regardless of the kernel tick rate.
+#[cfg(target_os = "linux")]
+const USER_HZ: u64 = 100;
+
+/// Operating system identifier of the calling thread, if supported on this platform.
+///
+/// On Linux this is the thread id (TID) shown by tools like `top -H` and `ps -L`.
+#[cfg(target_os = "linux")]
+pub(crate) fn current_os_thread_id() -> Option<u64> {
+    // SAFETY: gettid takes no arguments, always succeeds and has no side effects.
+    let tid = unsafe { ::libc::syscall(::libc::SYS_gettid) };
+    u64::try_from(tid).ok()
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn current_os_thread_id() -> Option<u64> {
+    None
+}
+
+/// CPU time consumed so far by the thread of this process with the given OS id.
+#[cfg(target_os = "linux")]
+pub(crate) fn thread_cpu_time(os_id: u64) -> Option<Duration> {
+    let task = format!("/proc/self/task/{}", os_id);
+    // Prefer the nanosecond resolution time in schedstat, when the kernel provides it.
+    if let Ok(schedstat) = ::std::fs::read_to_string(format!("{}/schedstat", task)) {
+        if let Some(Ok(nanos)) = schedstat.split_whitespace().next().map(str::parse) {
+            return Some(Duration::from_nanos(nanos));
+        }
+    }
+    // The thread name in stat can contain spaces so skip past it before splitting.
+    let stat = ::std::fs::read_to_string(format!("{}/stat", task)).ok()?;
+    let fields: Vec<&str> = stat.rsplit_once(')')?.1.split_whitespace().collect();
+    let utime: u64 = fields.get(11)?.parse().ok()?;
+    let stime: u64 = fields.get(12)?.parse().ok()?;
+    let ticks = utime + stime;
+    Some(Duration::from_secs(ticks / USER_HZ) + Duration::from_millis(ticks % USER_HZ * 10))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn thread_cpu_time(_os_id: u64) -> Option<Duration> {
+    None
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use std::time::Duration;
+    use std::time::Instant;
+
+    use super::current_os_thread_id;
+    use super::thread_cpu_time;
+
+    #[test]
+    fn os_id_of_current_thread() {
+        let link = ::std::fs::read_link("/proc/thread-self").expect("thread-self to be available");
+        let tid = link
+            .file_name()
+            .and_then(|tid| tid.to_str())
+            .map(str::parse);
+        assert_eq!(Some(Ok(current_os_thread_id().expect("thread id"))), tid);
+    }
+
+    #[test]
+    fn cpu_time_of_current_thread() {
+        let os_id = current_os_thread_id().expect("thread id to be available");
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_millis(50) {
+            ::std::hint::spin_loop();
+        }
+        let cpu = thread_cpu_time(os_id).expect("cpu time to be available");
+        assert!(cpu > Duration::from_millis(10));
+        assert_eq!(None, thread_cpu_time(u64::MAX));
+    }
+}
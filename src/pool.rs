@@ -0,0 +1,152 @@
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+
+use super::Builder;
+use super::Result;
+use super::Thread;
+use super::ThreadGroup;
+use super::ThreadScope;
+
+/// Spawn threads on behalf of thread pool libraries.
+///
+/// Pool threads are created with a [`Builder`] so they are registered for introspection
+/// and can report activity like any other thread.
+/// All threads are spawned into a [`ThreadGroup`] named after the pool and their
+/// names are generated from the pool name and the thread index.
+///
+/// Pool libraries usually run their own loop in each thread so shutdown requests
+/// are only seen by pool jobs that explicitly check for them.
+/// Jobs can report activity and check for shutdown requests with thread-local
+/// helpers such as [`activity`] and [`should_shutdown`].
+///
+/// [`Builder`]: struct.Builder.html
+/// [`ThreadGroup`]: struct.ThreadGroup.html
+/// [`activity`]: fn.activity.html
+/// [`should_shutdown`]: fn.should_shutdown.html
+pub struct PoolSpawner {
+    group: ThreadGroup,
+    next_index: AtomicUsize,
+    prefix: String,
+}
+
+impl PoolSpawner {
+    pub fn new<S: Into<String>>(prefix: S) -> PoolSpawner {
+        let prefix = prefix.into();
+        PoolSpawner {
+            group: ThreadGroup::new(prefix.clone()),
+            next_index: AtomicUsize::new(0),
+            prefix,
+        }
+    }
+
+    /// Group all pool threads are spawned into.
+    pub fn group(&self) -> &ThreadGroup {
+        &self.group
+    }
+
+    /// Spawn a pool thread.
+    ///
+    /// The thread is named `<prefix>-<index>`, if the pool does not provide an index
+    /// one is generated by counting spawned threads.
+    pub fn spawn<F>(&self, index: Option<usize>, f: F) -> Result<Thread<()>>
+    where
+        F: FnOnce(ThreadScope),
+        F: Send + 'static,
+    {
+        let index = index.unwrap_or_else(|| self.next_index.fetch_add(1, Ordering::Relaxed));
+        let name = format!("{}-{}", self.prefix, index);
+        Builder::new(name).group(&self.group).spawn(f)
+    }
+
+    /// Spawn handler for [`rayon::ThreadPoolBuilder::spawn_handler`].
+    ///
+    /// Thread names configured on the rayon pool are used as full names.
+    ///
+    /// [`rayon::ThreadPoolBuilder::spawn_handler`]: https://docs.rs/rayon/*/rayon/struct.ThreadPoolBuilder.html#method.spawn_handler
+    #[cfg(feature = "with_rayon")]
+    pub fn rayon_handler(&self) -> impl FnMut(rayon::ThreadBuilder) -> ::std::io::Result<()> {
+        let group = self.group.clone();
+        let prefix = self.prefix.clone();
+        move |thread| {
+            let name = format!("{}-{}", prefix, thread.index());
+            let mut builder = Builder::new(name).group(&group);
+            if let Some(full_name) = thread.name() {
+                builder = builder.full_name(full_name);
+            }
+            builder
+                .spawn(move |_| thread.run())
+                .map(|_| ())
+                .map_err(|error| ::std::io::Error::other(error.to_string()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::PoolSpawner;
+
+    #[test]
+    fn generated_names() {
+        let pool = PoolSpawner::new("generated_names");
+        let thread0 = pool
+            .spawn(None, |scope| loop {
+                ::std::thread::sleep(Duration::from_millis(10));
+                if scope.should_shutdown() {
+                    break;
+                }
+            })
+            .expect("to spawn test thread");
+        let thread3 = pool.spawn(Some(3), |_| {}).expect("to spawn test thread");
+        thread3.join().expect("the thread to stop");
+
+        // Give threads a chance to register and collect list.
+        ::std::thread::sleep(Duration::from_millis(20));
+        let threads = pool.group().threads();
+        pool.group().request_shutdown();
+        thread0.join().expect("the thread to stop");
+
+        assert_eq!(1, threads.len());
+        assert_eq!("generated_names-0", threads[0].name);
+        assert_eq!(Some("generated_names".into()), threads[0].group);
+        assert_eq!(2, pool.group().status().exited);
+    }
+
+    #[cfg(feature = "with_rayon")]
+    #[test]
+    fn rayon_pool() {
+        let spawner = PoolSpawner::new("rayon_pool");
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(2)
+            .thread_name(|index| format!("rayon pool thread {}", index))
+            .spawn_handler(spawner.rayon_handler())
+            .build()
+            .expect("to build the rayon pool");
+        let mut threads = Vec::new();
+        for _ in 0..50 {
+            threads = spawner.group().threads();
+            if threads.len() == 2 {
+                break;
+            }
+            ::std::thread::sleep(Duration::from_millis(10));
+        }
+        let names = pool.broadcast(|_| ::std::thread::current().name().map(String::from));
+        drop(pool);
+        spawner
+            .group()
+            .join_timeout(Duration::from_secs(1))
+            .expect("the pool threads to stop");
+
+        let mut full_names: Vec<String> = threads.into_iter().map(|thread| thread.name).collect();
+        full_names.sort();
+        let mut names: Vec<String> = names.into_iter().flatten().collect();
+        names.sort();
+        assert_eq!(
+            vec!["rayon pool thread 0", "rayon pool thread 1"],
+            full_names
+        );
+        assert_eq!(vec!["rayon_pool-0", "rayon_pool-1"], names);
+        assert_eq!(2, spawner.group().status().exited);
+    }
+}
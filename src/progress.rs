@@ -0,0 +1,221 @@
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+use std::time::Duration;
+use std::time::SystemTime;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+/// Public view of the progress reported by a thread.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct ThreadProgress {
+    /// Units of work completed so far.
+    pub current: u64,
+
+    /// Estimated completion time, if enough progress was made to compute it.
+    pub eta: Option<SystemTime>,
+
+    /// Units of work completed per second since progress tracking started.
+    pub throughput: Option<f64>,
+
+    /// Total units of work to complete.
+    pub total: u64,
+}
+
+impl ThreadProgress {
+    /// Fraction of work completed, between 0 and 1.
+    pub fn ratio(&self) -> f64 {
+        if self.total == 0 {
+            return 1.0;
+        }
+        (self.current as f64 / self.total as f64).min(1.0)
+    }
+}
+
+/// Internal tracking of progress reported by a thread.
+///
+/// Completed work is updated with atomic operations only so it can be reported in tight loops.
+/// The total and start time change together and are protected by a lock.
+#[derive(Default)]
+pub(crate) struct ProgressState {
+    current: AtomicU64,
+    started: Mutex<Option<ProgressStart>>,
+}
+
+/// Total units of work and start time of the progress being tracked.
+#[derive(Clone, Copy)]
+struct ProgressStart {
+    started: SystemTime,
+    total: u64,
+}
+
+impl ProgressState {
+    /// Record `count` more units of work as completed.
+    pub(crate) fn advance(&self, count: u64) {
+        self.current.fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// Stop tracking progress.
+    pub(crate) fn clear(&self) {
+        *self.lock() = None;
+    }
+
+    /// Start tracking progress towards `total` units of work.
+    pub(crate) fn start(&self, total: u64) {
+        let mut started = self.lock();
+        self.current.store(0, Ordering::Relaxed);
+        *started = Some(ProgressStart {
+            started: SystemTime::now(),
+            total,
+        });
+    }
+
+    /// Compute the progress report, if progress is tracked.
+    ///
+    /// Completed work is counted from the time progress tracking started so throughput
+    /// is computed from that time only, regardless of the activities of the thread.
+    pub(crate) fn snapshot(&self) -> Option<ThreadProgress> {
+        let (current, start) = {
+            let started = self.lock();
+            (self.current.load(Ordering::Relaxed), (*started)?)
+        };
+        let total = start.total;
+        let now = SystemTime::now();
+        let elapsed = now
+            .duration_since(start.started)
+            .unwrap_or_default()
+            .as_secs_f64();
+        let throughput = if elapsed > 0.0 && current > 0 {
+            Some(current as f64 / elapsed)
+        } else {
+            None
+        };
+        let eta = throughput.map(|throughput| {
+            let remaining = total.saturating_sub(current) as f64;
+            now + Duration::from_secs_f64(remaining / throughput)
+        });
+        Some(ThreadProgress {
+            current,
+            eta,
+            throughput,
+            total,
+        })
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Option<ProgressStart>> {
+        self.started
+            .lock()
+            .expect("ProgressState::started lock poisoned")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use std::time::SystemTime;
+
+    use super::super::registered_threads;
+    use super::super::registry::current_thread_id;
+    use super::super::Builder;
+    use super::ProgressStart;
+    use super::ProgressState;
+
+    #[test]
+    fn not_tracked() {
+        let state = ProgressState::default();
+        assert!(state.snapshot().is_none());
+        state.start(10);
+        state.clear();
+        assert!(state.snapshot().is_none());
+    }
+
+    #[test]
+    fn throughput_and_eta() {
+        let state = ProgressState::default();
+        state.start(100);
+        *state.lock() = Some(ProgressStart {
+            started: SystemTime::now() - Duration::from_secs(10),
+            total: 100,
+        });
+        state.advance(20);
+        state.advance(5);
+        let progress = state.snapshot().expect("progress to be tracked");
+        assert_eq!(25, progress.current);
+        assert_eq!(100, progress.total);
+        assert_eq!(0.25, progress.ratio());
+        let throughput = progress.throughput.expect("throughput to be computed");
+        assert!((throughput - 2.5).abs() < 0.1);
+        let eta = progress
+            .eta
+            .expect("eta to be computed")
+            .duration_since(SystemTime::now())
+            .expect("eta to be in the future");
+        assert!(eta > Duration::from_secs(29) && eta < Duration::from_secs(31));
+
+        // Activities that start after progress tracking do not shorten the throughput window.
+        let thread = Builder::new("progress_activities_started_after_progress")
+            .spawn(|scope| {
+                scope.progress(100);
+                ::std::thread::sleep(Duration::from_millis(50));
+                scope.advance(25);
+                let _finishing = scope.scoped_activity("finishing");
+                let id = current_thread_id();
+                registered_threads()
+                    .into_iter()
+                    .find(|status| status.id == id)
+                    .and_then(|status| status.progress)
+            })
+            .expect("to spawn test thread");
+        let progress = thread
+            .join()
+            .expect("the thread to stop")
+            .expect("progress to be reported");
+
+        // 25 units took at least 50 milliseconds so throughput is at most 500 units per second.
+        let throughput = progress.throughput.expect("throughput to be computed");
+        assert!(throughput > 0.0 && throughput <= 500.0);
+        let eta = progress
+            .eta
+            .expect("eta to be computed")
+            .duration_since(SystemTime::now())
+            .expect("eta to be in the future");
+        assert!(eta >= Duration::from_millis(140));
+    }
+
+    #[test]
+    fn nested_item_activities() {
+        let thread = Builder::new("progress_nested_item_activities")
+            .spawn(|scope| {
+                let _migration = scope.scoped_activity("migrating rows");
+                scope.progress(20);
+                for row in 0..10 {
+                    let _row = scope.scoped_activity_with("migrating row", vec![("row", row)]);
+                    ::std::thread::sleep(Duration::from_millis(5));
+                    scope.advance(1);
+                }
+                let _row = scope.scoped_activity("migrating row");
+                let id = current_thread_id();
+                registered_threads()
+                    .into_iter()
+                    .find(|status| status.id == id)
+                    .and_then(|status| status.progress)
+            })
+            .expect("to spawn test thread");
+        let progress = thread
+            .join()
+            .expect("the thread to stop")
+            .expect("progress to be reported");
+
+        // 10 rows took at least 50 milliseconds so throughput is at most 200 rows per second.
+        let throughput = progress.throughput.expect("throughput to be computed");
+        assert!(throughput > 0.0 && throughput <= 200.0);
+        let eta = progress
+            .eta
+            .expect("eta to be computed")
+            .duration_since(SystemTime::now())
+            .expect("eta to be in the future");
+        assert!(eta >= Duration::from_millis(40));
+    }
+}
@@ -0,0 +1,334 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use super::registered_threads;
+use super::registry_counters;
+use super::RegistryCounters;
+use super::ThreadStatus;
+
+/// Labels set by the renderer itself, thread labels with these names are ignored.
+const RESERVED_LABELS: &[&str] = &["activity", "group", "id", "name", "quantile", "state"];
+
+/// Render the thread registry in the Prometheus text exposition format.
+///
+/// The following metrics are exported:
+///
+///   * `humthreads_threads`: number of registered threads by name and state (`busy` or `idle`).
+///   * `humthreads_thread_info`: always 1, with the thread identity, current activity
+///     and labels as labels.
+///   * `humthreads_activity_latency_seconds`: summary of scoped activity durations by thread.
+///   * `humthreads_threads_spawned_total`, `humthreads_threads_exited_total` and
+///     `humthreads_threads_panicked_total`: lifetime counters kept by the registry.
+///
+/// Thread labels are converted to valid Prometheus label names and are ignored
+/// if they clash with labels set by the renderer.
+pub fn prometheus_metrics() -> String {
+    render_prometheus(&registered_threads(), &registry_counters())
+}
+
+/// Render the given thread snapshots and counters in the Prometheus text exposition format.
+///
+/// See [`prometheus_metrics`] for the list of exported metrics.
+///
+/// [`prometheus_metrics`]: fn.prometheus_metrics.html
+pub fn render_prometheus(threads: &[ThreadStatus], counters: &RegistryCounters) -> String {
+    let mut out = String::new();
+    let mut threads: Vec<&ThreadStatus> = threads.iter().collect();
+    threads.sort_by(|a, b| a.name.cmp(&b.name).then(a.id.cmp(&b.id)));
+
+    // Thread counts by name and state.
+    let mut counts: BTreeMap<&str, (usize, usize)> = BTreeMap::new();
+    for thread in &threads {
+        let count = counts.entry(thread.name.as_str()).or_default();
+        if thread.activity.is_some() {
+            count.0 += 1;
+        } else {
+            count.1 += 1;
+        }
+    }
+    write_header(
+        &mut out,
+        "humthreads_threads",
+        "gauge",
+        "Number of registered threads by name and state.",
+    );
+    for (name, (busy, idle)) in counts {
+        for (state, count) in [("busy", busy), ("idle", idle)] {
+            let labels = [("name", name), ("state", state)];
+            write_sample(&mut out, "humthreads_threads", &labels, &[], count);
+        }
+    }
+
+    // Thread identity and activity.
+    write_header(
+        &mut out,
+        "humthreads_thread_info",
+        "gauge",
+        "Identity, activity and labels of registered threads.",
+    );
+    for thread in &threads {
+        let id = thread.id.to_string();
+        let labels = [
+            ("activity", thread.activity.as_deref().unwrap_or("")),
+            ("group", thread.group.as_deref().unwrap_or("")),
+            ("id", id.as_str()),
+            ("name", thread.name.as_str()),
+        ];
+        write_sample(
+            &mut out,
+            "humthreads_thread_info",
+            &labels,
+            &thread_labels(thread),
+            1,
+        );
+    }
+
+    // Scoped activity latencies.
+    write_header(
+        &mut out,
+        "humthreads_activity_latency_seconds",
+        "summary",
+        "Duration of completed scoped activities.",
+    );
+    for thread in &threads {
+        let id = thread.id.to_string();
+        for (activity, summary) in &thread.latencies {
+            let quantiles = [
+                ("0.5", summary.p50),
+                ("0.99", summary.p99),
+                ("1", summary.max),
+            ];
+            for (quantile, duration) in quantiles {
+                let labels = [
+                    ("activity", activity.as_str()),
+                    ("id", id.as_str()),
+                    ("name", thread.name.as_str()),
+                    ("quantile", quantile),
+                ];
+                write_sample(
+                    &mut out,
+                    "humthreads_activity_latency_seconds",
+                    &labels,
+                    &[],
+                    duration.as_secs_f64(),
+                );
+            }
+            let labels = [
+                ("activity", activity.as_str()),
+                ("id", id.as_str()),
+                ("name", thread.name.as_str()),
+            ];
+            write_sample(
+                &mut out,
+                "humthreads_activity_latency_seconds_count",
+                &labels,
+                &[],
+                summary.count,
+            );
+        }
+    }
+
+    // Lifetime registry counters.
+    let totals = [
+        (
+            "humthreads_threads_spawned_total",
+            "Number of threads registered since the process started.",
+            counters.spawned,
+        ),
+        (
+            "humthreads_threads_exited_total",
+            "Number of threads that exited normally since the process started.",
+            counters.exited,
+        ),
+        (
+            "humthreads_threads_panicked_total",
+            "Number of threads that exited because of a panic since the process started.",
+            counters.panicked,
+        ),
+    ];
+    for (name, help, value) in totals {
+        write_header(&mut out, name, "counter", help);
+        write_sample(&mut out, name, &[], &[], value);
+    }
+    out
+}
+
+/// Escape a label value as required by the text exposition format.
+fn escape_label_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Convert an arbitrary string into a valid label name.
+///
+/// Invalid characters are replaced with `_` and names starting with a digit are prefixed with `_`.
+fn label_name(name: &str) -> String {
+    let mut label: String = name
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if label.is_empty() || label.starts_with(|c: char| c.is_ascii_digit()) {
+        label.insert(0, '_');
+    }
+    label
+}
+
+/// Thread labels converted to valid label names, without those reserved by the renderer.
+fn thread_labels(thread: &ThreadStatus) -> Vec<(String, &str)> {
+    let mut labels: Vec<(String, &str)> = thread
+        .labels
+        .iter()
+        .map(|(key, value)| (label_name(key), value.as_str()))
+        .filter(|(key, _)| !key.starts_with("__") && !RESERVED_LABELS.contains(&key.as_str()))
+        .collect();
+    // Keys may clash after conversion so keep only the first one.
+    labels.sort_by(|a, b| a.0.cmp(&b.0));
+    labels.dedup_by(|a, b| a.0 == b.0);
+    labels
+}
+
+fn write_header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn write_sample<V: ::std::fmt::Display>(
+    out: &mut String,
+    name: &str,
+    labels: &[(&str, &str)],
+    extra: &[(String, &str)],
+    value: V,
+) {
+    out.push_str(name);
+    let labels = labels
+        .iter()
+        .map(|(key, value)| (*key, *value))
+        .chain(extra.iter().map(|(key, value)| (key.as_str(), *value)));
+    let mut first = true;
+    for (key, value) in labels {
+        out.push(if first { '{' } else { ',' });
+        first = false;
+        let _ = write!(out, "{}=\"{}\"", key, escape_label_value(value));
+    }
+    if !first {
+        out.push('}');
+    }
+    let _ = writeln!(out, " {}", value);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::time::Duration;
+
+    use super::super::registered_threads;
+    use super::super::Builder;
+    use super::super::LatencySummary;
+    use super::super::RegistryCounters;
+    use super::escape_label_value;
+    use super::label_name;
+    use super::render_prometheus;
+
+    #[test]
+    fn escaping() {
+        assert_eq!(
+            "a \\\"quoted\\\" \\\\ value\\n",
+            escape_label_value("a \"quoted\" \\ value\n")
+        );
+        assert_eq!("shard_id", label_name("shard-id"));
+        assert_eq!("_1st", label_name("1st"));
+        assert_eq!("_", label_name(""));
+    }
+
+    #[test]
+    fn render_threads() {
+        let thread = Builder::new("render_threads")
+            .full_name("prometheus \"render\" threads")
+            .label("shard-id", "4")
+            .label("name", "ignored")
+            .spawn(|scope| {
+                scope.activity("waiting");
+                while !scope.should_shutdown() {
+                    ::std::thread::sleep(Duration::from_millis(10));
+                }
+            })
+            .expect("to spawn test thread");
+
+        // Give the thread a chance to register and collect its status.
+        ::std::thread::sleep(Duration::from_millis(50));
+        let mut threads: Vec<_> = registered_threads()
+            .into_iter()
+            .filter(|status| status.short_name == "render_threads")
+            .collect();
+        thread.request_shutdown();
+        thread.join().expect("the thread to stop");
+
+        assert_eq!(1, threads.len());
+        let id = threads[0].id;
+        let mut latencies = BTreeMap::new();
+        latencies.insert(
+            "request".to_string(),
+            LatencySummary {
+                count: 3,
+                max: Duration::from_millis(1500),
+                p50: Duration::from_millis(250),
+                p99: Duration::from_millis(1000),
+            },
+        );
+        threads[0].latencies = latencies;
+        let counters = RegistryCounters {
+            exited: 2,
+            panicked: 1,
+            spawned: 4,
+        };
+        let text = render_prometheus(&threads, &counters);
+        let name = "prometheus \\\"render\\\" threads";
+        let expected = vec![
+            "# TYPE humthreads_threads gauge".to_string(),
+            format!(r#"humthreads_threads{{name="{}",state="busy"}} 1"#, name),
+            format!(r#"humthreads_threads{{name="{}",state="idle"}} 0"#, name),
+            format!(
+                r#"humthreads_thread_info{{activity="waiting",group="",id="{}",name="{}",shard_id="4"}} 1"#,
+                id, name
+            ),
+            format!(
+                r#"humthreads_activity_latency_seconds{{activity="request",id="{}",name="{}",quantile="0.5"}} 0.25"#,
+                id, name
+            ),
+            format!(
+                r#"humthreads_activity_latency_seconds{{activity="request",id="{}",name="{}",quantile="1"}} 1.5"#,
+                id, name
+            ),
+            format!(
+                r#"humthreads_activity_latency_seconds_count{{activity="request",id="{}",name="{}"}} 3"#,
+                id, name
+            ),
+            "# TYPE humthreads_threads_spawned_total counter".to_string(),
+            "humthreads_threads_spawned_total 4".to_string(),
+            "humthreads_threads_exited_total 2".to_string(),
+            "humthreads_threads_panicked_total 1".to_string(),
+        ];
+        for line in expected {
+            assert!(
+                text.lines().any(|l| l == line),
+                "missing {}\n{}",
+                line,
+                text
+            );
+        }
+    }
+}
@@ -0,0 +1,241 @@
+use super::os::thread_cpu_time;
+use super::registry::registered_threads_select;
+use super::thread_history;
+use super::ActivityValue;
+use super::ThreadGroup;
+use super::ThreadStatus;
+
+/// Select registered threads matching a set of conditions.
+///
+/// All conditions must match for a thread to be selected.
+/// A query without conditions selects all registered threads.
+#[derive(Clone, Debug, Default)]
+pub struct ThreadQuery {
+    cpu_time: bool,
+    fields: Vec<(String, ActivityValue)>,
+    group: Option<String>,
+    history: bool,
+    name: Option<String>,
+}
+
+impl ThreadQuery {
+    pub fn new() -> ThreadQuery {
+        ThreadQuery::default()
+    }
+
+    /// Select threads with an activity, at any level of the stack, with the given field value.
+    pub fn field<K, V>(mut self, key: K, value: V) -> ThreadQuery
+    where
+        K: Into<String>,
+        V: Into<ActivityValue>,
+    {
+        self.fields.push((key.into(), value.into()));
+        self
+    }
+
+    /// Select threads spawned in the named [`ThreadGroup`].
+    ///
+    /// [`ThreadGroup`]: struct.ThreadGroup.html
+    pub fn group<S: Into<String>>(mut self, group: S) -> ThreadQuery {
+        self.group = Some(group.into());
+        self
+    }
+
+    /// Include the CPU time consumed by selected threads in [`ThreadStatus::cpu_time`].
+    ///
+    /// The CPU time is read from the operating system for each selected thread,
+    /// which is more expensive than the rest of the snapshot.
+    ///
+    /// [`ThreadStatus::cpu_time`]: struct.ThreadStatus.html#structfield.cpu_time
+    pub fn with_cpu_time(mut self, cpu_time: bool) -> ThreadQuery {
+        self.cpu_time = cpu_time;
+        self
+    }
+
+    /// Include the activity history of selected threads in [`ThreadStatus::history`].
+    ///
+    /// [`ThreadStatus::history`]: struct.ThreadStatus.html#structfield.history
+    pub fn with_history(mut self, history: bool) -> ThreadQuery {
+        self.history = history;
+        self
+    }
+
+    /// Check if a thread status matches the query.
+    pub fn matches(&self, status: &ThreadStatus) -> bool {
+        self.matches_thread(&status.name, status.group.as_deref()) && self.matches_fields(status)
+    }
+
+    /// Check the conditions on the activity fields of a thread.
+    fn matches_fields(&self, status: &ThreadStatus) -> bool {
+        self.fields.iter().all(|(key, value)| {
+            status
+                .activity_stack
+                .iter()
+                .any(|frame| frame.fields.get(key) == Some(value))
+        })
+    }
+
+    /// Check the conditions on the name and group of a thread.
+    fn matches_thread(&self, name: &str, group: Option<&str>) -> bool {
+        if let Some(expected) = self.group.as_deref() {
+            if group != Some(expected) {
+                return false;
+            }
+        }
+        match self.name.as_deref() {
+            Some(expected) => name.contains(expected),
+            None => true,
+        }
+    }
+
+    /// Select threads with a full name that contains the given string.
+    pub fn name_contains<S: Into<String>>(mut self, name: S) -> ThreadQuery {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Return a snapshot of the current status of threads matching the query.
+    ///
+    /// Threads are filtered by name and group before their status is collected
+    /// and inspect callbacks are only invoked for threads matching the query.
+    pub fn run(&self) -> Vec<ThreadStatus> {
+        registered_threads_select(
+            |status| self.matches_thread(status.name(), status.group().map(ThreadGroup::name)),
+            |status| self.matches_fields(status),
+        )
+        .into_iter()
+        .map(|mut status| {
+            if self.cpu_time {
+                status.cpu_time = status.os_id.and_then(thread_cpu_time);
+            }
+            if self.history {
+                status.history = thread_history(status.id).unwrap_or_default();
+            }
+            status
+        })
+        .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use super::super::ActivityFields;
+    use super::super::Builder;
+    use super::ThreadQuery;
+
+    #[test]
+    fn filter_by_fields() {
+        let mut threads = Vec::new();
+        for task in 0..3 {
+            let thread = Builder::new(format!("filter_by_fields_{}", task))
+                .spawn(move |scope| {
+                    let _request =
+                        scope.scoped_activity_with("handling request", vec![("request", 17)]);
+                    let _task = scope.scoped_activity_with("processing task", vec![("task", task)]);
+                    while !scope.should_shutdown() {
+                        ::std::thread::sleep(Duration::from_millis(10));
+                    }
+                })
+                .expect("to spawn test thread");
+            threads.push(thread);
+        }
+
+        // Give threads a chance to register and query them.
+        ::std::thread::sleep(Duration::from_millis(20));
+        let request = ThreadQuery::new()
+            .name_contains("filter_by_fields")
+            .field("request", 17)
+            .run();
+        let task = ThreadQuery::new()
+            .name_contains("filter_by_fields")
+            .field("request", 17)
+            .field("task", 1)
+            .run();
+        let missing = ThreadQuery::new()
+            .name_contains("filter_by_fields")
+            .field("task", "1")
+            .run();
+        for thread in threads {
+            thread.request_shutdown();
+            thread.join().expect("the thread to stop");
+        }
+
+        assert_eq!(3, request.len());
+        assert_eq!(1, task.len());
+        assert_eq!("filter_by_fields_1", task[0].name);
+        assert_eq!(Some(&1.into()), task[0].activity_fields.get("task"));
+        assert!(missing.is_empty());
+    }
+
+    #[test]
+    fn only_inspect_selected_threads() {
+        let inspected = Arc::new(AtomicUsize::new(0));
+        let thread_inspected = Arc::clone(&inspected);
+        let thread = Builder::new("only_inspect_selected_threads")
+            .spawn(move |scope| {
+                scope.on_inspect(move || {
+                    thread_inspected.fetch_add(1, Ordering::SeqCst);
+                    ActivityFields::new()
+                });
+                let _task = scope.scoped_activity_with("processing task", vec![("task", 1)]);
+                while !scope.should_shutdown() {
+                    ::std::thread::sleep(Duration::from_millis(10));
+                }
+            })
+            .expect("to spawn test thread");
+
+        // Give the thread a chance to register and query it.
+        ::std::thread::sleep(Duration::from_millis(20));
+        let other_name = ThreadQuery::new()
+            .name_contains("only_inspect_selected_threads_other")
+            .run();
+        let other_group = ThreadQuery::new()
+            .name_contains("only_inspect_selected_threads")
+            .group("only_inspect_selected_threads")
+            .run();
+        let other_field = ThreadQuery::new()
+            .name_contains("only_inspect_selected_threads")
+            .field("task", 2)
+            .run();
+        let not_inspected = inspected.load(Ordering::SeqCst);
+        let selected = ThreadQuery::new()
+            .name_contains("only_inspect_selected_threads")
+            .run();
+        thread.request_shutdown();
+        thread.join().expect("the thread to stop");
+
+        assert!(other_name.is_empty());
+        assert!(other_group.is_empty());
+        assert!(other_field.is_empty());
+        assert_eq!(0, not_inspected);
+        assert_eq!(1, selected.len());
+        assert_eq!(1, inspected.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn cpu_time_on_request() {
+        let thread = Builder::new("cpu_time_on_request")
+            .spawn(|scope| {
+                while !scope.should_shutdown() {
+                    ::std::thread::sleep(Duration::from_millis(10));
+                }
+            })
+            .expect("to spawn test thread");
+
+        // Give the thread a chance to register and query it.
+        ::std::thread::sleep(Duration::from_millis(20));
+        let query = ThreadQuery::new().name_contains("cpu_time_on_request");
+        let without = query.clone().run();
+        let with = query.with_cpu_time(true).run();
+        thread.request_shutdown();
+        thread.join().expect("the thread to stop");
+
+        assert_eq!(None, without[0].cpu_time);
+        assert_eq!(cfg!(target_os = "linux"), with[0].cpu_time.is_some());
+    }
+}
@@ -1,56 +1,334 @@
 use std::collections::hash_map::DefaultHasher;
 use std::collections::HashMap;
+use std::collections::VecDeque;
 use std::hash::Hash;
 use std::hash::Hasher;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
 use std::sync::Mutex;
+use std::thread::ThreadId;
 
+use serde::Deserialize;
+use serde::Serialize;
+
+use super::activity::ActivityState;
+use super::history::ActivityRecord;
+use super::lifecycle;
 use super::status::RegisteredStatus;
 use super::status::ThreadStatus;
+use super::status::ThreadTreeNode;
+
+/// Maximum number of panicked threads to remember.
+const PANICKED_THREADS_LIMIT: usize = 32;
+
+static EXITED_THREADS: AtomicU64 = AtomicU64::new(0);
+static PANICKED_THREADS_COUNT: AtomicU64 = AtomicU64::new(0);
+static SPAWNED_THREADS: AtomicU64 = AtomicU64::new(0);
+
+/// Number of threads registered and deregistered since the process started.
+#[derive(Clone, Copy, Default, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+pub struct RegistryCounters {
+    /// Number of threads that exited normally.
+    pub exited: u64,
+
+    /// Number of threads that exited because of a panic.
+    pub panicked: u64,
+
+    /// Number of threads registered, including those registered with `register_current_thread`.
+    pub spawned: u64,
+}
 
 lazy_static::lazy_static! {
-    static ref THREADS_REGISTRY: Mutex<HashMap<u64, RegisteredStatus>> = {
+    static ref PANICKED_THREADS: Mutex<VecDeque<ThreadStatus>> = {
+        Mutex::new(VecDeque::new())
+    };
+    // Statuses are shared so snapshots can be rendered after the registry is unlocked.
+    static ref THREADS_REGISTRY: Mutex<HashMap<u64, Arc<RegisteredStatus>>> = {
         Mutex::new(HashMap::new())
     };
 }
 
 /// Return the current thread id as an unsigned integer.
 pub(crate) fn current_thread_id() -> u64 {
-    let id = ::std::thread::current().id();
+    thread_id(::std::thread::current().id())
+}
+
+/// Return the given thread id as an unsigned integer.
+pub(crate) fn thread_id(id: ThreadId) -> u64 {
     let mut hasher = DefaultHasher::new();
     id.hash(&mut hasher);
     hasher.finish()
 }
 
 /// Removes thread state information for the specified thread.
-pub(crate) fn deregister_thread(id: u64) {
-    THREADS_REGISTRY
+///
+/// If the thread is exiting because of a panic its final status is kept,
+/// together with its activity history, and returned by `panicked_threads`.
+pub(crate) fn deregister_thread(id: u64, panicked: bool) {
+    let status = THREADS_REGISTRY
         .lock()
         .expect("global THREADS_REGISTRY lock poisoned")
         .remove(&id);
+    if let Some(status) = status.as_ref() {
+        lifecycle::exited(id, status.name(), panicked);
+        #[cfg(feature = "with_trace")]
+        {
+            status.flush_trace();
+            super::trace::thread_exited(id, status.name());
+        }
+    }
+    let status = match status {
+        Some(status) if panicked => status,
+        Some(_) => {
+            EXITED_THREADS.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        None => return,
+    };
+    PANICKED_THREADS_COUNT.fetch_add(1, Ordering::Relaxed);
+    // Avoid a double panic (and an abort) if the status can't be converted.
+    let status = ::std::panic::catch_unwind(::std::panic::AssertUnwindSafe(|| {
+        let mut snapshot = ThreadStatus::from(&*status);
+        snapshot.history = status.history();
+        snapshot
+    }));
+    if let Ok(status) = status {
+        let mut panicked = match PANICKED_THREADS.lock() {
+            Ok(panicked) => panicked,
+            Err(_) => return,
+        };
+        if panicked.len() == PANICKED_THREADS_LIMIT {
+            panicked.pop_front();
+        }
+        panicked.push_back(status);
+    }
+}
+
+/// Check if the thread with the given id is registered.
+pub(crate) fn is_registered(id: u64) -> bool {
+    THREADS_REGISTRY
+        .lock()
+        .expect("global THREADS_REGISTRY lock poisoned")
+        .contains_key(&id)
 }
 
 /// Insert thread state information for a new thread.
 pub(crate) fn register_thread(id: u64, status: RegisteredStatus) {
+    lifecycle::spawned(id, status.name());
+    let mut registry = THREADS_REGISTRY
+        .lock()
+        .expect("global THREADS_REGISTRY lock poisoned");
+    registry.insert(id, Arc::new(status));
+    // Count the thread once it is visible so counter changes reflect registry changes.
+    SPAWNED_THREADS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Signal the registered thread with the given id it should terminate as soon as possible.
+///
+/// Returns `false` if no thread with the given id is registered.
+/// Like [`Thread::request_shutdown`], the thread is responsible for checking the request.
+///
+/// [`Thread::request_shutdown`]: struct.Thread.html#method.request_shutdown
+pub fn request_thread_shutdown(id: u64) -> bool {
+    let name = THREADS_REGISTRY
+        .lock()
+        .expect("global THREADS_REGISTRY lock poisoned")
+        .get(&id)
+        .map(|status| {
+            status.request_shutdown();
+            status.name().to_string()
+        });
+    match name {
+        Some(name) => {
+            lifecycle::shutdown_requested(id, &name);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Return the id and full name of registered threads matching the given filter.
+#[cfg(feature = "with_tracing")]
+pub(crate) fn registered_names<F>(filter: F) -> Vec<(u64, String)>
+where
+    F: Fn(&RegisteredStatus) -> bool,
+{
     THREADS_REGISTRY
         .lock()
         .expect("global THREADS_REGISTRY lock poisoned")
-        .insert(id, status);
+        .iter()
+        .filter(|(_, status)| filter(status))
+        .map(|(id, status)| (*id, status.name().to_string()))
+        .collect()
+}
+
+/// Return the final status of the most recent threads that exited because of a panic.
+///
+/// Threads are listed from the oldest to the most recent panic and include their
+/// activity history, if it was enabled with [`Builder::history`].
+/// Only the last 32 panicked threads are remembered.
+///
+/// [`Builder::history`]: struct.Builder.html#method.history
+pub fn panicked_threads() -> Vec<ThreadStatus> {
+    PANICKED_THREADS
+        .lock()
+        .expect("global PANICKED_THREADS lock poisoned")
+        .iter()
+        .cloned()
+        .collect()
+}
+
+/// Return the number of threads registered and deregistered since the process started.
+pub fn registry_counters() -> RegistryCounters {
+    RegistryCounters {
+        exited: EXITED_THREADS.load(Ordering::Relaxed),
+        panicked: PANICKED_THREADS_COUNT.load(Ordering::Relaxed),
+        spawned: SPAWNED_THREADS.load(Ordering::Relaxed),
+    }
 }
 
 /// Return a snapshot of the current status of threads.
 pub fn registered_threads() -> Vec<ThreadStatus> {
+    registered_threads_filter(|_| true)
+}
+
+/// Return a snapshot of the current status of threads matching the given filter.
+///
+/// Inspect callbacks registered by threads are invoked after the registry is unlocked.
+pub(crate) fn registered_threads_filter<F>(filter: F) -> Vec<ThreadStatus>
+where
+    F: Fn(&RegisteredStatus) -> bool,
+{
+    registered_threads_select(filter, |_| true)
+}
+
+/// Return a snapshot of the current status of threads matching both filters.
+///
+/// `filter` is checked before the status of a thread is collected and `select`
+/// is checked on the collected status, before inspect callbacks are invoked,
+/// so callbacks are only invoked for selected threads.
+pub(crate) fn registered_threads_select<F, S>(filter: F, select: S) -> Vec<ThreadStatus>
+where
+    F: Fn(&RegisteredStatus) -> bool,
+    S: Fn(&ThreadStatus) -> bool,
+{
+    let threads: Vec<Arc<RegisteredStatus>> = THREADS_REGISTRY
+        .lock()
+        .expect("global THREADS_REGISTRY lock poisoned")
+        .values()
+        .filter(|status| filter(status))
+        .cloned()
+        .collect();
+    // Collect statuses after the registry is unlocked: they read CPU times from the OS.
+    threads
+        .into_iter()
+        .map(|status| (ThreadStatus::from(&*status), status.inspectors()))
+        .filter(|(status, _)| select(status))
+        .map(|(mut status, inspectors)| {
+            inspectors.inspect(&mut status);
+            status
+        })
+        .collect()
+}
+
+/// Return the most recent activities completed by the registered thread with the given id.
+///
+/// Returns `None` if no thread with the given id is registered.
+/// The history is empty unless the thread was spawned with [`Builder::history`].
+///
+/// [`Builder::history`]: struct.Builder.html#method.history
+pub fn thread_history(id: u64) -> Option<Vec<ActivityRecord>> {
+    let status = THREADS_REGISTRY
+        .lock()
+        .expect("global THREADS_REGISTRY lock poisoned")
+        .get(&id)
+        .cloned();
+    status.map(|status| status.history())
+}
+
+/// Return the full name and activity tracking state of all registered threads, by thread id.
+///
+/// Activity stacks can be read from the returned states without locking the registry.
+pub(crate) fn activity_states() -> Vec<(u64, String, Arc<ActivityState>)> {
     THREADS_REGISTRY
         .lock()
         .expect("global THREADS_REGISTRY lock poisoned")
         .iter()
-        .map(|(_, status)| status.into())
+        .map(|(id, status)| (*id, status.name().to_string(), status.activity_state()))
+        .collect()
+}
+
+/// Return a snapshot of the current status of threads without invoking inspect callbacks.
+///
+/// Threads whose status can't be collected, for example because a lock was poisoned
+/// by a panic, are skipped and reported by returning `false` with the snapshot.
+/// This is suitable for use while unwinding.
+#[cfg(feature = "with_crash_report")]
+pub(crate) fn uninspected_threads() -> (Vec<ThreadStatus>, bool) {
+    let registered: Vec<Arc<RegisteredStatus>> = match THREADS_REGISTRY.lock() {
+        Ok(registry) => registry.values().cloned().collect(),
+        Err(_) => return (Vec::new(), false),
+    };
+    let threads: Vec<ThreadStatus> = registered
+        .iter()
+        .filter_map(|status| {
+            ::std::panic::catch_unwind(::std::panic::AssertUnwindSafe(|| {
+                ThreadStatus::from(&**status)
+            }))
+            .ok()
+        })
+        .collect();
+    let complete = threads.len() == registered.len();
+    (threads, complete)
+}
+
+/// Return a snapshot of the current status of threads organised by parent thread.
+///
+/// Threads spawned by a registered thread are listed as children of that thread.
+/// Threads without a parent, or whose parent has exited, are returned as roots.
+pub fn thread_tree() -> Vec<ThreadTreeNode> {
+    let threads = registered_threads();
+    let ids: Vec<u64> = threads.iter().map(|thread| thread.id).collect();
+    let mut roots = Vec::new();
+    let mut children: HashMap<u64, Vec<ThreadStatus>> = HashMap::new();
+    for thread in threads {
+        match thread.parent {
+            Some(parent) if ids.contains(&parent) => {
+                children.entry(parent).or_default().push(thread)
+            }
+            _ => roots.push(thread),
+        }
+    }
+    build_tree_nodes(roots, &mut children)
+}
+
+/// Recursively attach children to the given threads, sorted by name for stable output.
+fn build_tree_nodes(
+    mut threads: Vec<ThreadStatus>,
+    children: &mut HashMap<u64, Vec<ThreadStatus>>,
+) -> Vec<ThreadTreeNode> {
+    threads.sort_by(|a, b| a.name.cmp(&b.name).then(a.id.cmp(&b.id)));
+    threads
+        .into_iter()
+        .map(|thread| {
+            let nested = children.remove(&thread.id).unwrap_or_default();
+            let children = build_tree_nodes(nested, children);
+            ThreadTreeNode { children, thread }
+        })
         .collect()
 }
 
 #[cfg(test)]
 mod tests {
+    use super::super::ActivityReport;
     use super::super::Builder;
+    use super::super::ThreadQuery;
+    use super::panicked_threads;
     use super::registered_threads;
+    use super::request_thread_shutdown;
+    use super::thread_history;
+    use super::thread_tree;
 
     #[test]
     fn thread_registration_lifecycle() {
@@ -78,11 +356,154 @@ mod tests {
         let thread = running_threads
             .into_iter()
             .find(|t| t.short_name == "thread_registration_lifecycle");
-        assert_eq!(true, thread.is_some());
+        assert!(thread.is_some());
         assert_eq!("thread registration lifecycle long", thread.unwrap().name);
         let thread = stopped_threads
             .into_iter()
             .find(|t| t.short_name == "thread_registration_lifecycle");
-        assert_eq!(false, thread.is_some());
+        assert!(thread.is_none());
+    }
+
+    #[test]
+    fn thread_tree_links_children() {
+        let (notifier, notification) = ::crossbeam_channel::bounded::<()>(0);
+        let parent = Builder::new("thread_tree_links_children")
+            .spawn(move |_| {
+                let child = Builder::new("thread_tree_links_children_child")
+                    .spawn(move |_| {
+                        let _ = notification.recv();
+                    })
+                    .expect("to spawn child thread");
+                child.join().expect("the child thread to stop");
+            })
+            .expect("to spawn parent thread");
+
+        // Give threads a chance to register and collect the tree.
+        ::std::thread::sleep(::std::time::Duration::from_millis(50));
+        let roots = thread_tree();
+        drop(notifier);
+        parent.join().expect("the parent thread to stop");
+
+        // Assert test results.
+        let node = roots
+            .into_iter()
+            .find(|node| node.thread.name == "thread_tree_links_children")
+            .expect("parent thread not found");
+        assert_eq!(1, node.children.len());
+        let child = &node.children[0];
+        assert_eq!("thread_tree_links_children_child", child.thread.name);
+        assert_eq!(Some(node.thread.id), child.thread.parent);
+    }
+
+    #[test]
+    fn history_of_running_and_panicked_threads() {
+        let (notifier, notification) = ::crossbeam_channel::bounded::<()>(0);
+        let thread = Builder::new("history_of_running_and_panicked_threads")
+            .history(4)
+            .spawn(move |scope| {
+                scope.activity("first");
+                scope.activity("second");
+                let _ = notification.recv();
+                scope.activity("third");
+                panic!("this panic is expected");
+            })
+            .expect("to spawn test thread");
+
+        // Give the thread a chance to register and collect its history.
+        ::std::thread::sleep(::std::time::Duration::from_millis(50));
+        let running = ThreadQuery::new()
+            .name_contains("history_of_running_and_panicked_threads")
+            .with_history(true)
+            .run();
+        let history = running.first().and_then(|status| thread_history(status.id));
+        drop(notifier);
+        assert!(thread.join().is_err());
+
+        // Assert test results.
+        assert_eq!(1, running.len());
+        assert_eq!(1, running[0].history.len());
+        assert_eq!("first", running[0].history[0].activity);
+        assert_eq!(Some(running[0].history.clone()), history);
+        let panicked = panicked_threads()
+            .into_iter()
+            .find(|status| status.id == running[0].id)
+            .expect("panicked thread not found");
+        let activities: Vec<String> = panicked
+            .history
+            .into_iter()
+            .map(|record| record.activity)
+            .collect();
+        assert_eq!(vec!["first", "second"], activities);
+        assert_eq!(Some("third".into()), panicked.activity);
+        assert_eq!(None, thread_history(running[0].id));
+    }
+
+    #[test]
+    fn shutdown_by_id() {
+        let (notifier, notification) = ::crossbeam_channel::bounded::<()>(0);
+        let thread = Builder::new("shutdown_by_id")
+            .spawn(move |scope| {
+                while !scope.should_shutdown() {
+                    ::std::thread::sleep(::std::time::Duration::from_millis(10));
+                }
+                let _ = notification.recv();
+            })
+            .expect("to spawn test thread");
+
+        // Give the thread a chance to register and find its id.
+        ::std::thread::sleep(::std::time::Duration::from_millis(50));
+        let id = registered_threads()
+            .into_iter()
+            .find(|status| status.name == "shutdown_by_id")
+            .expect("thread not found")
+            .id;
+        assert!(request_thread_shutdown(id));
+        let requested = registered_threads()
+            .into_iter()
+            .find(|status| status.id == id)
+            .map(|status| status.shutdown_requested);
+        drop(notifier);
+        thread.join().expect("the thread to stop");
+        assert!(!request_thread_shutdown(id));
+        assert_eq!(Some(true), requested);
+    }
+
+    struct PanickingReport;
+
+    impl ActivityReport for PanickingReport {
+        fn describe(&self) -> String {
+            panic!("this panic is expected");
+        }
+    }
+
+    #[test]
+    fn panicking_typed_activity() {
+        let (notifier, notification) = ::crossbeam_channel::bounded::<()>(0);
+        let thread = Builder::new("panicking_typed_activity")
+            .spawn(move |scope| {
+                scope.typed_activity(PanickingReport);
+                let _ = notification.recv();
+            })
+            .expect("to spawn test thread");
+
+        // Give the thread a chance to register and take a snapshot.
+        ::std::thread::sleep(::std::time::Duration::from_millis(50));
+        let status = registered_threads()
+            .into_iter()
+            .find(|status| status.name == "panicking_typed_activity")
+            .expect("thread not found");
+        drop(notifier);
+        thread.join().expect("the thread to stop");
+
+        // The registry is still usable.
+        Builder::new("panicking_typed_activity_after")
+            .spawn(|_| {})
+            .expect("to spawn test thread")
+            .join()
+            .expect("the thread to stop");
+        assert_eq!(
+            Some("(activity report panicked: this panic is expected)".into()),
+            status.activity
+        );
     }
 }
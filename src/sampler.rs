@@ -0,0 +1,227 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use super::activity::ActivityState;
+use super::registry::activity_states;
+use super::registry::current_thread_id;
+use super::registry_counters;
+use super::Builder;
+use super::Result;
+use super::Thread;
+use super::ThreadScope;
+
+/// Frame used for threads sampled without an activity.
+const IDLE_FRAME: &str = "(idle)";
+
+/// Maximum number of distinct stacks counted, to bound memory with dynamic activity names.
+const MAX_STACKS: usize = 4096;
+
+/// Stack reporting the samples of stacks seen after `MAX_STACKS` was reached.
+const OVERFLOW_STACK: &str = "(other stacks)";
+
+/// Profile where threads spend wall-clock time by sampling their reported activities.
+///
+/// A `humthreads-sampler` thread reads the activity stack of every registered thread
+/// at a fixed interval and counts how many times each stack was seen.
+/// Threads need no additional instrumentation beyond reporting their activities.
+///
+/// Samples are aggregated in the "folded stacks" format used by flamegraph tools:
+/// one `thread;outer activity;inner activity count` line per distinct stack.
+/// Threads without an activity are reported with a single `(idle)` frame.
+///
+/// Activity stacks are read from each thread's own state and the registry is only
+/// locked to refresh the list of threads when threads register or exit.
+/// At most 4096 distinct stacks are counted: samples of any other stack, which can
+/// happen when activity names include dynamic values, are counted as `(other stacks)`.
+pub struct ActivitySampler {
+    samples: Arc<Mutex<Samples>>,
+    thread: Thread<()>,
+}
+
+impl ActivitySampler {
+    /// Start sampling activity stacks every `interval`.
+    pub fn start(interval: Duration) -> Result<ActivitySampler> {
+        let samples = Arc::new(Mutex::new(Samples::default()));
+        let thread_samples = Arc::clone(&samples);
+        let thread = Builder::new("humthreads-sampler")
+            .full_name("humthreads activity sampler")
+            .spawn(move |scope| sample(interval, &thread_samples, &scope))?;
+        Ok(ActivitySampler { samples, thread })
+    }
+
+    /// Samples collected so far, in the folded stacks format.
+    pub fn folded(&self) -> String {
+        self.samples
+            .lock()
+            .expect("ActivitySampler::samples lock poisoned")
+            .folded()
+    }
+
+    /// Stop sampling and return all collected samples, in the folded stacks format.
+    pub fn stop(self) -> Result<String> {
+        self.thread.request_shutdown();
+        self.thread.join()?;
+        Ok(self.folded())
+    }
+}
+
+/// Count of samples by folded stack.
+#[derive(Default)]
+struct Samples {
+    /// Samples of stacks not counted because `MAX_STACKS` was reached.
+    overflow: u64,
+    stacks: BTreeMap<String, u64>,
+}
+
+impl Samples {
+    fn folded(&self) -> String {
+        let mut folded = String::new();
+        for (stack, count) in &self.stacks {
+            let _ = writeln!(folded, "{} {}", stack, count);
+        }
+        if self.overflow > 0 {
+            let _ = writeln!(folded, "{} {}", OVERFLOW_STACK, self.overflow);
+        }
+        folded
+    }
+
+    /// Record a sample of a thread's activity stack.
+    fn record(&mut self, thread: &str, activities: &[String]) {
+        let mut stack = frame(thread);
+        if activities.is_empty() {
+            stack.push(';');
+            stack.push_str(IDLE_FRAME);
+        }
+        for activity in activities {
+            stack.push(';');
+            stack.push_str(&frame(activity));
+        }
+        let full = self.stacks.len() >= MAX_STACKS;
+        match self.stacks.get_mut(&stack) {
+            Some(count) => *count += 1,
+            None if full => self.overflow += 1,
+            None => {
+                self.stacks.insert(stack, 1);
+            }
+        }
+    }
+}
+
+/// Make a name safe to use as a frame, `;` separates frames and lines separate stacks.
+fn frame(name: &str) -> String {
+    name.replace(';', ":").replace(['\n', '\r'], " ")
+}
+
+/// Sample registered threads, other than the sampler itself, until shutdown is requested.
+fn sample(interval: Duration, samples: &Mutex<Samples>, scope: &ThreadScope) {
+    let sampler = current_thread_id();
+    let mut counters = None;
+    let mut threads: Vec<(String, Arc<ActivityState>)> = Vec::new();
+    let mut next = Instant::now();
+    while !scope.should_shutdown() {
+        // Only lock the registry when threads were registered or deregistered.
+        let current = registry_counters();
+        if counters != Some(current) {
+            counters = Some(current);
+            threads = activity_states()
+                .into_iter()
+                .filter(|(id, _, _)| *id != sampler)
+                .map(|(_, name, state)| (name, state))
+                .collect();
+        }
+        let stacks: Vec<(&str, Vec<String>)> = threads
+            .iter()
+            .map(|(name, state)| {
+                let activities = state
+                    .snapshot()
+                    .frames
+                    .into_iter()
+                    .map(|frame| frame.activity)
+                    .collect();
+                (name.as_str(), activities)
+            })
+            .collect();
+        {
+            let mut samples = samples
+                .lock()
+                .expect("ActivitySampler::samples lock poisoned");
+            for (thread, activities) in stacks {
+                samples.record(thread, &activities);
+            }
+        }
+        // Keep a steady rate regardless of how long sampling took.
+        next += interval;
+        let now = Instant::now();
+        if next > now {
+            ::std::thread::sleep(next - now);
+        } else {
+            next = now;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use std::time::Instant;
+
+    use super::super::Builder;
+    use super::ActivitySampler;
+    use super::Samples;
+    use super::MAX_STACKS;
+
+    #[test]
+    fn folded_format() {
+        let mut samples = Samples::default();
+        samples.record("worker", &["outer".into(), "inner;with separator".into()]);
+        samples.record("worker", &["outer".into(), "inner;with separator".into()]);
+        samples.record("worker", &[]);
+        assert_eq!(
+            "worker;(idle) 1\nworker;outer;inner:with separator 2\n",
+            samples.folded()
+        );
+    }
+
+    #[test]
+    fn distinct_stacks_are_capped() {
+        let mut samples = Samples::default();
+        for task in 0..MAX_STACKS + 10 {
+            samples.record("worker", &[format!("processing task {}", task)]);
+        }
+        samples.record("worker", &["processing task 0".into()]);
+        let folded = samples.folded();
+        assert_eq!(MAX_STACKS + 1, folded.lines().count());
+        assert!(folded.contains("worker;processing task 0 2\n"));
+        assert!(folded.ends_with("(other stacks) 10\n"));
+    }
+
+    #[test]
+    fn sample_threads() {
+        let sampler = ActivitySampler::start(Duration::from_millis(1)).expect("to start");
+        let thread = Builder::new("sample_threads")
+            .spawn(|scope| {
+                let _outer = scope.scoped_activity("outer");
+                let _inner = scope.scoped_activity("inner");
+                let start = Instant::now();
+                while start.elapsed() < Duration::from_millis(50) {
+                    ::std::thread::sleep(Duration::from_millis(1));
+                }
+            })
+            .expect("to spawn test thread");
+        thread.join().expect("the thread to stop");
+        let folded = sampler.stop().expect("the sampler to stop");
+
+        let count: u64 = folded
+            .lines()
+            .find_map(|line| line.strip_prefix("sample_threads;outer;inner "))
+            .expect("stack not sampled")
+            .parse()
+            .expect("valid sample count");
+        assert!(count > 0);
+        assert!(!folded.contains("humthreads activity sampler"));
+    }
+}
@@ -0,0 +1,182 @@
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Write;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+
+use failure::ResultExt;
+
+use super::super::ActivityRecord;
+use super::super::ErrorKind;
+use super::super::Result;
+use super::super::ThreadStatus;
+use super::SocketCommand;
+use super::SocketReply;
+use super::SocketRequest;
+use super::SocketResponse;
+use super::SOCKET_PROTOCOL_VERSION;
+
+/// Query a [`SocketServer`] running in another process.
+///
+/// [`SocketServer`]: struct.SocketServer.html
+pub struct SocketClient {
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+}
+
+impl SocketClient {
+    /// Connect to the server listening on the given socket.
+    pub fn connect<P: AsRef<Path>>(path: P) -> Result<SocketClient> {
+        let writer = UnixStream::connect(path).with_context(|_| ErrorKind::SocketClient)?;
+        let reader = writer
+            .try_clone()
+            .with_context(|_| ErrorKind::SocketClient)?;
+        Ok(SocketClient {
+            reader: BufReader::new(reader),
+            writer,
+        })
+    }
+
+    /// Activity history of the thread with the given id, `None` if the thread is not registered.
+    pub fn history(&mut self, id: u64) -> Result<Option<Vec<ActivityRecord>>> {
+        match self.request(SocketCommand::History { id })? {
+            SocketReply::History { history, .. } => Ok(history),
+            reply => Err(unexpected(reply)),
+        }
+    }
+
+    /// Send a command to the server and wait for its reply.
+    ///
+    /// Error replies from the server are returned as [`ErrorKind::SocketReply`] errors.
+    ///
+    /// [`ErrorKind::SocketReply`]: enum.ErrorKind.html#variant.SocketReply
+    pub fn request(&mut self, command: SocketCommand) -> Result<SocketReply> {
+        let mut request = serde_json::to_string(&SocketRequest::new(command))
+            .with_context(|_| ErrorKind::SocketClient)?;
+        request.push('\n');
+        self.writer
+            .write_all(request.as_bytes())
+            .with_context(|_| ErrorKind::SocketClient)?;
+        let mut line = String::new();
+        let read = self
+            .reader
+            .read_line(&mut line)
+            .with_context(|_| ErrorKind::SocketClient)?;
+        if read == 0 {
+            return Err(ErrorKind::SocketReply("connection closed by the server".into()).into());
+        }
+        let response: SocketResponse =
+            serde_json::from_str(&line).with_context(|_| ErrorKind::SocketClient)?;
+        if response.version != SOCKET_PROTOCOL_VERSION {
+            let message = format!("unsupported protocol version {}", response.version);
+            return Err(ErrorKind::SocketReply(message).into());
+        }
+        match response.reply {
+            SocketReply::Error { message } => Err(ErrorKind::SocketReply(message).into()),
+            reply => Ok(reply),
+        }
+    }
+
+    /// Signal the thread with the given id it should terminate.
+    ///
+    /// Returns `false` if the thread is not registered.
+    pub fn request_shutdown(&mut self, id: u64) -> Result<bool> {
+        match self.request(SocketCommand::Shutdown { id })? {
+            SocketReply::Shutdown { found, .. } => Ok(found),
+            reply => Err(unexpected(reply)),
+        }
+    }
+
+    /// Current status of all threads registered in the server process.
+    pub fn snapshot(&mut self) -> Result<Vec<ThreadStatus>> {
+        self.request_snapshot(false)
+    }
+
+    /// Current status of all threads registered in the server process, with their CPU time.
+    pub fn snapshot_with_cpu_time(&mut self) -> Result<Vec<ThreadStatus>> {
+        self.request_snapshot(true)
+    }
+
+    fn request_snapshot(&mut self, cpu_time: bool) -> Result<Vec<ThreadStatus>> {
+        match self.request(SocketCommand::Snapshot { cpu_time })? {
+            SocketReply::Snapshot { threads } => Ok(threads),
+            reply => Err(unexpected(reply)),
+        }
+    }
+}
+
+fn unexpected(reply: SocketReply) -> super::super::Error {
+    ErrorKind::SocketReply(format!("unexpected reply: {:?}", reply)).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::BufRead;
+    use std::io::BufReader;
+    use std::io::Write;
+    use std::os::unix::fs::PermissionsExt;
+    use std::os::unix::net::UnixStream;
+    use std::time::Duration;
+
+    use super::super::super::Builder;
+    use super::super::SocketServer;
+    use super::SocketClient;
+
+    #[test]
+    fn query_server() {
+        let path = ::std::env::temp_dir().join(format!("humthreads-{}.sock", ::std::process::id()));
+        let server = SocketServer::start(&path).expect("to start the server");
+        let mode = ::std::fs::metadata(&path)
+            .expect("the socket to exist")
+            .permissions()
+            .mode();
+        let thread = Builder::new("socket_query_server")
+            .history(4)
+            .spawn(|scope| {
+                scope.activity("first");
+                scope.activity("second");
+                while !scope.should_shutdown() {
+                    ::std::thread::sleep(Duration::from_millis(10));
+                }
+            })
+            .expect("to spawn test thread");
+
+        // Give the thread a chance to register and query it.
+        ::std::thread::sleep(Duration::from_millis(50));
+        let mut client = SocketClient::connect(&path).expect("to connect to the server");
+        let threads = client.snapshot().expect("to fetch a snapshot");
+        let id = threads
+            .iter()
+            .find(|thread| thread.name == "socket_query_server")
+            .expect("thread not found")
+            .id;
+        let history = client.history(id).expect("to fetch the history");
+        let found = client.request_shutdown(id).expect("to request shutdown");
+        thread.join().expect("the thread to stop");
+        let missing = client.history(id).expect("to fetch the history");
+
+        // Send a request for an unsupported version once the client is done.
+        drop(client);
+        let mut stream = UnixStream::connect(&path).expect("to connect to the server");
+        stream
+            .write_all(b"{\"version\":0,\"command\":\"snapshot\"}\n")
+            .expect("to send the request");
+        let mut unsupported = String::new();
+        BufReader::new(stream)
+            .read_line(&mut unsupported)
+            .expect("to read the response");
+        server.stop().expect("the server to stop");
+
+        assert_eq!(0o600, mode & 0o777);
+        assert!(threads
+            .iter()
+            .any(|thread| thread.short_name == "humthreads-socket"));
+        let history = history.expect("thread not found");
+        assert_eq!(1, history.len());
+        assert_eq!("first", history[0].activity);
+        assert!(found);
+        assert_eq!(None, missing);
+        assert!(unsupported.starts_with(r#"{"version":1,"result":"error","message":"unsupported"#));
+        assert!(!path.exists());
+    }
+}
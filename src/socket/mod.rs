@@ -0,0 +1,16 @@
+//! Introspection of the thread registry over a Unix domain socket.
+//!
+//! Requests and responses are JSON documents, one per line.
+//! Each message carries the protocol version so clients and servers
+//! can detect incompatible peers.
+mod client;
+mod protocol;
+mod server;
+
+pub use self::client::SocketClient;
+pub use self::protocol::SocketCommand;
+pub use self::protocol::SocketReply;
+pub use self::protocol::SocketRequest;
+pub use self::protocol::SocketResponse;
+pub use self::protocol::SOCKET_PROTOCOL_VERSION;
+pub use self::server::SocketServer;
@@ -0,0 +1,121 @@
+use serde::Deserialize;
+use serde::Serialize;
+
+use super::super::ActivityRecord;
+use super::super::ThreadStatus;
+
+/// Version of the socket protocol implemented by this crate.
+///
+/// Servers reject requests for a different version with a [`SocketReply::Error`].
+///
+/// [`SocketReply::Error`]: enum.SocketReply.html#variant.Error
+pub const SOCKET_PROTOCOL_VERSION: u32 = 1;
+
+/// Operations clients can request over the introspection socket.
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum SocketCommand {
+    /// Return the activity history of a registered thread.
+    History { id: u64 },
+
+    /// Signal a registered thread it should terminate.
+    Shutdown { id: u64 },
+
+    /// Return the current status of all registered threads.
+    ///
+    /// The CPU time of threads is only included if `cpu_time` is set.
+    Snapshot {
+        #[serde(default)]
+        cpu_time: bool,
+    },
+}
+
+/// Result of a [`SocketCommand`].
+///
+/// [`SocketCommand`]: enum.SocketCommand.html
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum SocketReply {
+    /// The request could not be processed.
+    Error { message: String },
+
+    /// Activity history of the thread, `None` if the thread is not registered.
+    History {
+        id: u64,
+        history: Option<Vec<ActivityRecord>>,
+    },
+
+    /// Outcome of a shutdown request, `found` is false if the thread is not registered.
+    Shutdown { id: u64, found: bool },
+
+    /// Current status of all registered threads.
+    Snapshot { threads: Vec<ThreadStatus> },
+}
+
+/// Message sent by clients, serialised as a single line of JSON.
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+pub struct SocketRequest {
+    /// Version of the protocol the client implements.
+    pub version: u32,
+
+    #[serde(flatten)]
+    pub command: SocketCommand,
+}
+
+impl SocketRequest {
+    /// Request the given command using the current protocol version.
+    pub fn new(command: SocketCommand) -> SocketRequest {
+        SocketRequest {
+            version: SOCKET_PROTOCOL_VERSION,
+            command,
+        }
+    }
+}
+
+/// Message sent by servers, serialised as a single line of JSON.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct SocketResponse {
+    /// Version of the protocol the server implements.
+    pub version: u32,
+
+    #[serde(flatten)]
+    pub reply: SocketReply,
+}
+
+impl SocketResponse {
+    /// Respond with the given reply using the current protocol version.
+    pub fn new(reply: SocketReply) -> SocketResponse {
+        SocketResponse {
+            version: SOCKET_PROTOCOL_VERSION,
+            reply,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SocketCommand;
+    use super::SocketReply;
+    use super::SocketRequest;
+    use super::SocketResponse;
+
+    #[test]
+    fn wire_format() {
+        let request = SocketRequest::new(SocketCommand::History { id: 4 });
+        let encoded = serde_json::to_string(&request).expect("to encode the request");
+        assert_eq!(r#"{"version":1,"command":"history","id":4}"#, encoded);
+        let request: SocketRequest = serde_json::from_str(r#"{"command":"snapshot","version":1}"#)
+            .expect("to decode the request");
+        assert_eq!(
+            SocketRequest::new(SocketCommand::Snapshot { cpu_time: false }),
+            request
+        );
+
+        let response = SocketResponse::new(SocketReply::Shutdown { id: 4, found: true });
+        let encoded = serde_json::to_string(&response).expect("to encode the response");
+        assert_eq!(
+            r#"{"version":1,"result":"shutdown","id":4,"found":true}"#,
+            encoded
+        );
+    }
+}
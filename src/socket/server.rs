@@ -0,0 +1,364 @@
+use std::fs;
+use std::io::ErrorKind as IoErrorKind;
+use std::io::Read;
+use std::io::Write;
+use std::os::unix::fs::DirBuilderExt;
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::UnixListener;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicUsize;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+
+use failure::ResultExt;
+
+use super::super::connections::ConnectionSlot;
+use super::super::request_thread_shutdown;
+use super::super::thread_history;
+use super::super::Builder;
+use super::super::ErrorKind;
+use super::super::Result;
+use super::super::Thread;
+use super::super::ThreadQuery;
+use super::super::ThreadScope;
+use super::SocketCommand;
+use super::SocketReply;
+use super::SocketRequest;
+use super::SocketResponse;
+use super::SOCKET_PROTOCOL_VERSION;
+
+/// Time to wait between checks for new connections and shutdown requests.
+const ACCEPT_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Maximum number of connections handled at the same time, others are dropped.
+const MAX_CONNECTIONS: usize = 16;
+
+/// Time a connected client can be silent before the connection is dropped.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Maximum size of a request, including the trailing newline.
+const MAX_REQUEST_SIZE: usize = 8 * 1024;
+
+/// Maximum duration of a connection, regardless of client activity.
+const SESSION_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Serve the thread registry over a Unix domain socket.
+///
+/// The server accepts connections on its own `humthreads-socket` thread, which is registered
+/// like any other humthreads thread, and serves each connection on a short lived
+/// `humthreads-socket-conn` thread.
+/// At most 16 connections are served at the same time and connections are closed after
+/// a minute, or after 5 seconds without requests.
+/// Clients send [`SocketRequest`]s and receive [`SocketResponse`]s, one JSON document per line.
+///
+/// The socket file is only accessible by the user owning the process (mode `0600`)
+/// and it is removed when the server is stopped or dropped.
+/// Requests are limited to 8 KiB.
+/// The socket is created in a private directory and only linked to the requested path
+/// once its permissions are set, so other users can't connect in the meantime.
+///
+/// [`SocketRequest`]: struct.SocketRequest.html
+/// [`SocketResponse`]: struct.SocketResponse.html
+pub struct SocketServer {
+    path: PathBuf,
+    thread: Option<Thread<()>>,
+}
+
+impl SocketServer {
+    /// Create the socket at the given path and start serving requests in a background thread.
+    ///
+    /// A stale socket left behind by a process that did not stop its server is replaced
+    /// but starting fails if another server is listening on the path or if the path
+    /// exists and is not a socket.
+    pub fn start<P: AsRef<Path>>(path: P) -> Result<SocketServer> {
+        let path = path.as_ref().to_path_buf();
+        let listener = bind_private(&path).with_context(|_| ErrorKind::SocketServer)?;
+        listener
+            .set_nonblocking(true)
+            .with_context(|_| ErrorKind::SocketServer)?;
+        let thread = Builder::new("humthreads-socket")
+            .full_name(format!("humthreads socket server on {}", path.display()))
+            .spawn(move |scope| serve(listener, scope))?;
+        Ok(SocketServer {
+            path,
+            thread: Some(thread),
+        })
+    }
+
+    /// Path of the socket the server is listening on.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Stop accepting requests, wait for the server thread to exit and remove the socket.
+    ///
+    /// Connections already being served are allowed to complete in the background.
+    pub fn stop(mut self) -> Result<()> {
+        self.shutdown()
+    }
+
+    fn shutdown(&mut self) -> Result<()> {
+        let thread = match self.thread.take() {
+            Some(thread) => thread,
+            None => return Ok(()),
+        };
+        thread.request_shutdown();
+        let result = thread.join();
+        let _ = fs::remove_file(&self.path);
+        result
+    }
+}
+
+impl Drop for SocketServer {
+    fn drop(&mut self) {
+        // Errors can't be reported when the server is dropped.
+        let _ = self.shutdown();
+    }
+}
+
+/// Bind a socket only accessible by the current user at the given path.
+///
+/// The socket is bound in a new `0700` directory next to the path, restricted to `0600`
+/// and then hard linked to the path, which fails instead of replacing existing files.
+fn bind_private(path: &Path) -> ::std::io::Result<UnixListener> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| ::std::io::Error::from(IoErrorKind::InvalidInput))?;
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let mut private = parent.as_os_str().to_owned();
+    private.push(format!(
+        "/.{}.{}",
+        name.to_string_lossy(),
+        ::std::process::id()
+    ));
+    let private = PathBuf::from(private);
+    fs::DirBuilder::new().mode(0o700).create(&private)?;
+    let bound = private.join("socket");
+    let result = UnixListener::bind(&bound).and_then(|listener| {
+        fs::set_permissions(&bound, fs::Permissions::from_mode(0o600))?;
+        link_socket(&bound, path)?;
+        Ok(listener)
+    });
+    let _ = fs::remove_file(&bound);
+    let _ = fs::remove_dir(&private);
+    result
+}
+
+/// Link the bound socket to the path, replacing stale sockets but not other files.
+fn link_socket(bound: &Path, path: &Path) -> ::std::io::Result<()> {
+    match fs::hard_link(bound, path) {
+        Err(ref error) if error.kind() == IoErrorKind::AlreadyExists => {
+            let stale = fs::symlink_metadata(path)?.file_type().is_socket()
+                && UnixStream::connect(path).is_err();
+            if !stale {
+                return Err(IoErrorKind::AddrInUse.into());
+            }
+            fs::remove_file(path)?;
+            fs::hard_link(bound, path)
+        }
+        result => result,
+    }
+}
+
+/// Accept connections until shutdown is requested and serve them in their own thread.
+fn serve(listener: UnixListener, scope: ThreadScope) {
+    let connections = Arc::new(AtomicUsize::new(0));
+    scope.static_activity("waiting for connections");
+    while !scope.should_shutdown() {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                // Drop connections over the limit by dropping the stream.
+                let slot = match ConnectionSlot::acquire(&connections, MAX_CONNECTIONS) {
+                    Some(slot) => slot,
+                    None => continue,
+                };
+                // The slot is released even if the thread fails to spawn.
+                let _ = Builder::new("humthreads-socket-conn")
+                    .full_name("humthreads socket connection")
+                    .spawn(move |scope| {
+                        let _slot = slot;
+                        // Errors are specific to a client so ignore them and move on.
+                        let _ = handle(stream, &scope);
+                    });
+            }
+            Err(_) => ::std::thread::sleep(ACCEPT_INTERVAL),
+        }
+    }
+}
+
+/// Respond to requests on the connection until the client disconnects or the session expires.
+fn handle(mut stream: UnixStream, scope: &ThreadScope) -> ::std::io::Result<()> {
+    let deadline = Instant::now() + SESSION_TIMEOUT;
+    stream.set_nonblocking(false)?;
+    stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut pending = Vec::new();
+    let mut buffer = [0; 1024];
+    loop {
+        // Serve the complete requests received so far.
+        while let Some(end) = pending.iter().position(|byte| *byte == b'\n') {
+            let line: Vec<u8> = pending.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            if line.trim().is_empty() {
+                continue;
+            }
+            let _activity = scope.scoped_activity("handling socket request");
+            respond(&mut stream, reply(&line))?;
+            if scope.should_shutdown() {
+                return Ok(());
+            }
+        }
+        if pending.len() > MAX_REQUEST_SIZE {
+            let reply = SocketReply::Error {
+                message: format!("request larger than {} bytes", MAX_REQUEST_SIZE),
+            };
+            return respond(&mut stream, reply);
+        }
+
+        // Bound the session length and not just the time between reads.
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining == Duration::ZERO {
+            return Ok(());
+        }
+        stream.set_read_timeout(Some(remaining.min(REQUEST_TIMEOUT)))?;
+        let read = stream.read(&mut buffer)?;
+        if read == 0 {
+            return Ok(());
+        }
+        pending.extend_from_slice(&buffer[..read]);
+    }
+}
+
+/// Decode a request and execute it.
+fn reply(line: &str) -> SocketReply {
+    match serde_json::from_str::<SocketRequest>(line) {
+        Ok(request) if request.version != SOCKET_PROTOCOL_VERSION => SocketReply::Error {
+            message: format!(
+                "unsupported protocol version {} (expected {})",
+                request.version, SOCKET_PROTOCOL_VERSION
+            ),
+        },
+        Ok(request) => execute(request.command),
+        Err(error) => SocketReply::Error {
+            message: format!("invalid request: {}", error),
+        },
+    }
+}
+
+/// Write a response to the client, one JSON document per line.
+fn respond(stream: &mut UnixStream, reply: SocketReply) -> ::std::io::Result<()> {
+    let response = SocketResponse::new(reply);
+    let mut response = serde_json::to_string(&response)?;
+    response.push('\n');
+    stream.write_all(response.as_bytes())
+}
+
+fn execute(command: SocketCommand) -> SocketReply {
+    match command {
+        SocketCommand::History { id } => SocketReply::History {
+            id,
+            history: thread_history(id),
+        },
+        SocketCommand::Shutdown { id } => SocketReply::Shutdown {
+            id,
+            found: request_thread_shutdown(id),
+        },
+        SocketCommand::Snapshot { cpu_time } => SocketReply::Snapshot {
+            threads: ThreadQuery::new().with_cpu_time(cpu_time).run(),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::BufRead;
+    use std::io::BufReader;
+    use std::io::Write;
+    use std::os::unix::net::UnixListener;
+    use std::os::unix::net::UnixStream;
+    use std::path::PathBuf;
+    use std::time::Duration;
+    use std::time::Instant;
+
+    use super::super::SocketClient;
+    use super::SocketServer;
+
+    fn socket_path(name: &str) -> PathBuf {
+        ::std::env::temp_dir().join(format!("humthreads-{}-{}.sock", name, ::std::process::id()))
+    }
+
+    #[test]
+    fn idle_clients_do_not_block_others() {
+        let path = socket_path("idle");
+        let server = SocketServer::start(&path).expect("to start the server");
+        let idle = UnixStream::connect(&path).expect("to connect to the server");
+        let start = Instant::now();
+        let mut client = SocketClient::connect(&path).expect("to connect to the server");
+        let threads = client.snapshot().expect("to fetch a snapshot");
+        let elapsed = start.elapsed();
+        drop(idle);
+        drop(client);
+        server.stop().expect("the server to stop");
+
+        assert!(elapsed < Duration::from_secs(1));
+        assert!(threads
+            .iter()
+            .any(|thread| thread.short_name == "humthreads-socket-conn"));
+    }
+
+    #[test]
+    fn large_requests_are_rejected() {
+        let path = socket_path("large");
+        let server = SocketServer::start(&path).expect("to start the server");
+        let mut stream = UnixStream::connect(&path).expect("to connect to the server");
+        // The server may close the connection before all of the request is sent.
+        let _ = stream.write_all(&vec![b'x'; 64 * 1024]);
+        let mut response = String::new();
+        let _ = BufReader::new(stream).read_line(&mut response);
+        server.stop().expect("the server to stop");
+
+        assert!(response.contains("request larger than 8192 bytes"));
+    }
+
+    #[test]
+    fn stop_on_drop() {
+        let path = socket_path("drop");
+        let server = SocketServer::start(&path).expect("to start the server");
+        drop(server);
+        assert!(!path.exists());
+        assert!(UnixStream::connect(&path).is_err());
+    }
+
+    #[test]
+    fn keep_files_that_are_not_sockets() {
+        let path = socket_path("regular-file");
+        fs::write(&path, "not a socket").expect("to create the file");
+        let result = SocketServer::start(&path);
+        let content = fs::read_to_string(&path).expect("the file to be kept");
+        let _ = fs::remove_file(&path);
+
+        assert!(result.is_err());
+        assert_eq!("not a socket", content);
+    }
+
+    #[test]
+    fn replace_stale_sockets() {
+        let path = socket_path("stale");
+        drop(UnixListener::bind(&path).expect("to create a stale socket"));
+        let server = SocketServer::start(&path).expect("to replace the stale socket");
+        let mut client = SocketClient::connect(&path).expect("to connect to the server");
+        let threads = client.snapshot();
+        drop(client);
+        server.stop().expect("the server to stop");
+
+        assert!(threads.is_ok());
+        assert!(!path.exists());
+    }
+}
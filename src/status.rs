@@ -1,83 +1,368 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
-use std::sync::Mutex;
+use std::time::Duration;
 
 use serde::Deserialize;
 use serde::Serialize;
 
+use super::activity::ActivityState;
+use super::inspect::ThreadInspectors;
+use super::metrics::ThreadMetrics;
+use super::os::current_os_thread_id;
+use super::progress::ProgressState;
+use super::ActivityFields;
+use super::ActivityFrame;
+use super::ActivityRecord;
+use super::LatencySummary;
+use super::ThreadGroup;
+use super::ThreadProgress;
+use super::ThreadScope;
+use super::ThreadUtilization;
+
+/// Optional tracking features for registered threads.
+#[derive(Clone, Default)]
+pub(crate) struct StatusConfig {
+    /// Number of completed activities to keep in the thread history.
+    pub(crate) history_size: usize,
+
+    /// Static labels attached to the thread.
+    pub(crate) labels: BTreeMap<String, String>,
+
+    /// Track time spent in each activity.
+    pub(crate) track_activity_time: bool,
+}
+
 /// Internal status tracking for registered threads.
 pub(crate) struct RegisteredStatus {
-    activity: Arc<Mutex<Option<String>>>,
+    activity: Arc<ActivityState>,
+    group: Option<ThreadGroup>,
+    id: u64,
+    inspectors: Arc<ThreadInspectors>,
+    labels: BTreeMap<String, String>,
+    metrics: Arc<ThreadMetrics>,
     name: String,
+    os_id: Option<u64>,
+    parent: Option<u64>,
+    progress: Arc<ProgressState>,
     short_name: String,
+    shutdown: Arc<AtomicBool>,
 }
 
 impl RegisteredStatus {
-    /// Provide mutable access to the thread's activity status attribute.
-    pub(crate) fn activity(&self) -> Arc<Mutex<Option<String>>> {
+    /// Activity tracking state shared with the thread.
+    pub(crate) fn activity_state(&self) -> Arc<ActivityState> {
         Arc::clone(&self.activity)
     }
 
-    pub(crate) fn new(name: String, short_name: String) -> RegisteredStatus {
-        let activity = Arc::new(Mutex::new(None));
+    /// Most recent activities completed by the thread, if history is enabled.
+    pub(crate) fn history(&self) -> Vec<ActivityRecord> {
+        self.activity.history()
+    }
+
+    /// Callbacks to invoke when building a snapshot of the thread.
+    pub(crate) fn inspectors(&self) -> Arc<ThreadInspectors> {
+        Arc::clone(&self.inspectors)
+    }
+
+    /// Group the thread was spawned into, if any.
+    pub(crate) fn group(&self) -> Option<&ThreadGroup> {
+        self.group.as_ref()
+    }
+
+    /// Create the status of the calling thread.
+    ///
+    /// Must be called by the thread being registered to capture its OS identifier.
+    pub(crate) fn new(
+        id: u64,
+        name: String,
+        short_name: String,
+        parent: Option<u64>,
+        group: Option<ThreadGroup>,
+        config: StatusConfig,
+        shutdown: Arc<AtomicBool>,
+    ) -> RegisteredStatus {
+        let activity = Arc::new(ActivityState::new(&config));
         RegisteredStatus {
             activity,
+            group,
+            id,
+            inspectors: Arc::new(ThreadInspectors::default()),
+            labels: config.labels,
+            metrics: Arc::new(ThreadMetrics::default()),
             name,
+            os_id: current_os_thread_id(),
+            parent,
+            progress: Arc::new(ProgressState::default()),
             short_name,
+            shutdown,
         }
     }
+
+    /// Registry id of the thread.
+    #[cfg(feature = "with_log")]
+    pub(crate) fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Static labels attached to the thread.
+    #[cfg(feature = "with_log")]
+    pub(crate) fn labels(&self) -> &BTreeMap<String, String> {
+        &self.labels
+    }
+
+    /// Push the activities buffered for the trace in progress to the recorder.
+    #[cfg(feature = "with_trace")]
+    pub(crate) fn flush_trace(&self) {
+        self.activity.flush_trace();
+    }
+
+    /// Full name of the thread.
+    pub(crate) fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Signal the thread it should terminate as soon as possible.
+    pub(crate) fn request_shutdown(&self) {
+        self.shutdown.store(true, Ordering::Relaxed);
+    }
+
+    /// Create a [`ThreadScope`] to report the state tracked by this status.
+    ///
+    /// [`ThreadScope`]: struct.ThreadScope.html
+    pub(crate) fn scope(&self) -> ThreadScope {
+        ThreadScope::new(
+            Arc::clone(&self.activity),
+            Arc::clone(&self.inspectors),
+            Arc::clone(&self.metrics),
+            Arc::clone(&self.progress),
+            Arc::clone(&self.shutdown),
+        )
+    }
 }
 
 /// Public view of a point in time status of a thread.
-#[derive(Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
 pub struct ThreadStatus {
     /// Description of the activity currently in progress by the thread.
     ///
     /// NOTE: threads are responsible for reporting their own activity.
     pub activity: Option<String>,
 
+    /// Structured fields attached to the current activity.
+    pub activity_fields: ActivityFields,
+
+    /// Number of scoped activities that ended out of order.
+    ///
+    /// Scoped activities are expected to end in the reverse order they started in.
+    /// A non-zero value indicates a [`ThreadScopeActivityGuard`] was dropped while
+    /// a more recent one was still alive.
+    ///
+    /// [`ThreadScopeActivityGuard`]: struct.ThreadScopeActivityGuard.html
+    pub activity_out_of_order: u64,
+
+    /// All activities currently in progress by the thread, from the outermost to the innermost.
+    ///
+    /// The innermost activity is the same as the `activity` attribute.
+    pub activity_stack: Vec<ActivityFrame>,
+
+    /// CPU time consumed by the thread so far, if supported on this platform.
+    ///
+    /// Reading the CPU time of threads is not free so it is only included in snapshots
+    /// when requested with [`ThreadQuery::with_cpu_time`].
+    ///
+    /// [`ThreadQuery::with_cpu_time`]: struct.ThreadQuery.html#method.with_cpu_time
+    #[serde(default)]
+    pub cpu_time: Option<Duration>,
+
+    /// Current value of counters published by the thread.
+    pub counters: BTreeMap<String, u64>,
+
+    /// Current value of gauges published by the thread.
+    pub gauges: BTreeMap<String, i64>,
+
+    /// Name of the [`ThreadGroup`] the thread was spawned into, if any.
+    ///
+    /// [`ThreadGroup`]: struct.ThreadGroup.html
+    pub group: Option<String>,
+
+    /// Most recent activities completed by the thread, from the oldest to the most recent.
+    ///
+    /// History is only recorded for threads spawned with [`Builder::history`] and
+    /// only included in snapshots when requested with [`ThreadQuery::with_history`].
+    ///
+    /// [`Builder::history`]: struct.Builder.html#method.history
+    /// [`ThreadQuery::with_history`]: struct.ThreadQuery.html#method.with_history
+    #[serde(default)]
+    pub history: Vec<ActivityRecord>,
+
+    /// Registry identifier of the thread.
+    pub id: u64,
+
+    /// Problems encountered while invoking the thread's inspect callbacks.
+    pub inspect_errors: Vec<String>,
+
+    /// Fields returned by the thread's inspect callbacks when the snapshot was taken.
+    ///
+    /// See [`ThreadScope::on_inspect`] for details.
+    ///
+    /// [`ThreadScope::on_inspect`]: struct.ThreadScope.html#method.on_inspect
+    pub inspected: ActivityFields,
+
+    /// Static labels attached to the thread with [`Builder::label`].
+    ///
+    /// [`Builder::label`]: struct.Builder.html#method.label
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+
+    /// Duration of completed scoped activities, by activity name.
+    pub latencies: BTreeMap<String, LatencySummary>,
+
     /// Full name of the thread.
     pub name: String,
 
+    /// Operating system identifier of the thread, if supported on this platform.
+    ///
+    /// On Linux this is the thread id (TID) used by tools such as `top -H`.
+    #[serde(default)]
+    pub os_id: Option<u64>,
+
+    /// Registry identifier of the thread that spawned this thread, if it was registered.
+    pub parent: Option<u64>,
+
+    /// Progress towards completion of the thread's work, if reported.
+    pub progress: Option<ThreadProgress>,
+
     /// OS name of the thread.
     ///
     /// This is called the short name because OS threads names usually have a limit.
     pub short_name: String,
+
+    /// The thread was requested to terminate but is still running.
+    #[serde(default)]
+    pub shutdown_requested: bool,
+
+    /// Time the thread spent busy and idle.
+    pub utilization: ThreadUtilization,
+}
+
+impl ThreadStatus {
+    /// Summarise the state of the thread as `stopping`, `busy` or `idle`.
+    ///
+    /// Threads that were requested to terminate are `stopping` regardless of their activity,
+    /// other threads are `busy` while they report an activity and `idle` otherwise.
+    pub fn state(&self) -> &'static str {
+        if self.shutdown_requested {
+            "stopping"
+        } else if self.activity.is_some() {
+            "busy"
+        } else {
+            "idle"
+        }
+    }
 }
 
 impl From<&RegisteredStatus> for ThreadStatus {
     fn from(status: &RegisteredStatus) -> ThreadStatus {
-        let activity = status
-            .activity
-            .lock()
-            .expect("RegisteredStatus::activity lock poisoned")
-            .clone();
+        let snapshot = status.activity.snapshot();
+        let activity = snapshot
+            .current
+            .as_ref()
+            .map(|frame| frame.activity.clone());
+        let activity_fields = snapshot
+            .current
+            .as_ref()
+            .map(|frame| frame.fields.clone())
+            .unwrap_or_default();
+        let progress = status.progress.snapshot();
         ThreadStatus {
             activity,
+            activity_fields,
+            activity_out_of_order: snapshot.out_of_order,
+            activity_stack: snapshot.frames,
+            counters: status.metrics.counters(),
+            cpu_time: None,
+            gauges: status.metrics.gauges(),
+            group: status.group.as_ref().map(|group| group.name().to_string()),
+            history: Vec::new(),
+            id: status.id,
+            inspect_errors: Vec::new(),
+            inspected: ActivityFields::new(),
+            labels: status.labels.clone(),
+            latencies: status.activity.latencies(),
             name: status.name.clone(),
+            os_id: status.os_id,
+            parent: status.parent,
+            progress,
             short_name: status.short_name.clone(),
+            shutdown_requested: status.shutdown.load(Ordering::Relaxed),
+            utilization: status.activity.utilization(),
         }
     }
 }
 
+/// Public view of a point in time status of a thread and the threads it spawned.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct ThreadTreeNode {
+    /// Registered threads spawned by this thread.
+    pub children: Vec<ThreadTreeNode>,
+
+    /// Status of the thread itself.
+    pub thread: ThreadStatus,
+}
+
 #[cfg(test)]
 mod tests {
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Arc;
+
+    use super::ActivityFields;
+    use super::ActivityFrame;
     use super::RegisteredStatus;
+    use super::StatusConfig;
     use super::ThreadStatus;
 
     #[test]
     fn from_register() {
-        let register = RegisteredStatus::new("long name".into(), "name".into());
+        let register = RegisteredStatus::new(
+            42,
+            "long name".into(),
+            "name".into(),
+            Some(4),
+            None,
+            StatusConfig::default(),
+            Arc::new(AtomicBool::new(false)),
+        );
         let status = ThreadStatus::from(&register);
         assert_eq!(status.activity, None);
+        assert!(status.activity_stack.is_empty());
+        assert!(status.counters.is_empty());
+        assert!(status.gauges.is_empty());
+        assert_eq!(status.group, None);
+        assert_eq!(status.id, 42);
         assert_eq!(status.name, "long name");
+        assert_eq!(status.parent, Some(4));
+        assert_eq!(status.progress, None);
         assert_eq!(status.short_name, "name");
     }
 
     #[test]
     fn report_activity() {
-        let register = RegisteredStatus::new("long name".into(), "name".into());
-        *register.activity.lock().unwrap() = Some("test".into());
+        let register = RegisteredStatus::new(
+            42,
+            "long name".into(),
+            "name".into(),
+            None,
+            None,
+            StatusConfig::default(),
+            Arc::new(AtomicBool::new(false)),
+        );
+        let frame = ActivityFrame::new("test".into(), ActivityFields::new());
+        register.activity.set(Some(frame.into()));
         let status = ThreadStatus::from(&register);
         assert_eq!(status.activity, Some("test".into()));
+        assert_eq!(status.activity_stack.len(), 1);
+        assert_eq!(status.activity_stack[0].activity, "test");
     }
 }
@@ -0,0 +1,130 @@
+use std::fmt::Write;
+use std::time::Duration;
+use std::time::SystemTime;
+
+use super::ThreadStatus;
+
+/// Render thread snapshots as a human readable, column aligned, text table.
+///
+/// Threads are listed in the given order with their id, name, group,
+/// current activity and for how long the activity has been in progress.
+pub fn text_table(threads: &[ThreadStatus]) -> String {
+    let now = SystemTime::now();
+    let header = ["ID", "NAME", "GROUP", "ACTIVITY", "SINCE"].map(String::from);
+    let mut rows = vec![header.to_vec()];
+    for thread in threads {
+        let since = thread
+            .activity_stack
+            .last()
+            .and_then(|frame| now.duration_since(frame.since).ok())
+            .map(format_age)
+            .unwrap_or_else(|| "-".into());
+        rows.push(vec![
+            thread.id.to_string(),
+            thread.name.clone(),
+            thread.group.clone().unwrap_or_else(|| "-".into()),
+            thread.activity.clone().unwrap_or_else(|| "(idle)".into()),
+            since,
+        ]);
+    }
+    align_columns(&rows)
+}
+
+/// Render rows of cells as column aligned text, one line per row.
+///
+/// Columns are separated by two spaces and padded to the widest cell in the column.
+/// This is the layout used by [`text_table`] and can be used to render custom tables.
+///
+/// [`text_table`]: fn.text_table.html
+pub fn align_columns(rows: &[Vec<String>]) -> String {
+    let columns = rows.iter().map(Vec::len).max().unwrap_or(0);
+    let mut widths = vec![0; columns];
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row.iter()) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let mut out = String::new();
+    for row in rows {
+        let mut line = String::new();
+        for (idx, cell) in row.iter().enumerate() {
+            if idx > 0 {
+                line.push_str("  ");
+            }
+            let _ = write!(line, "{:width$}", cell, width = widths[idx]);
+        }
+        out.push_str(line.trim_end());
+        out.push('\n');
+    }
+    out
+}
+
+/// Format a duration with the two most significant units (`1h2m`, `3m4s`, `5.6s`, `7ms`).
+///
+/// This is the format used by [`text_table`] for the age of activities.
+///
+/// [`text_table`]: fn.text_table.html
+pub fn format_age(age: Duration) -> String {
+    let secs = age.as_secs();
+    if secs >= 3600 {
+        format!("{}h{}m", secs / 3600, secs % 3600 / 60)
+    } else if secs >= 60 {
+        format!("{}m{}s", secs / 60, secs % 60)
+    } else if secs > 0 {
+        format!("{}.{}s", secs, age.subsec_millis() / 100)
+    } else {
+        format!("{}ms", age.subsec_millis())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use super::super::status::RegisteredStatus;
+    use super::super::status::StatusConfig;
+    use super::super::ThreadGroup;
+    use super::super::ThreadStatus;
+    use super::format_age;
+    use super::text_table;
+
+    #[test]
+    fn ages() {
+        assert_eq!("7ms", format_age(Duration::from_millis(7)));
+        assert_eq!("5.6s", format_age(Duration::from_millis(5600)));
+        assert_eq!("3m4s", format_age(Duration::from_secs(184)));
+        assert_eq!("1h2m", format_age(Duration::from_secs(3720)));
+    }
+
+    #[test]
+    fn aligned_columns() {
+        let register = RegisteredStatus::new(
+            12,
+            "worker-1".into(),
+            "worker-1".into(),
+            None,
+            Some(ThreadGroup::new("workers")),
+            StatusConfig::default(),
+            Arc::new(AtomicBool::new(false)),
+        );
+        register.scope().activity("working");
+        let idle = RegisteredStatus::new(
+            3,
+            "main".into(),
+            "main".into(),
+            None,
+            None,
+            StatusConfig::default(),
+            Arc::new(AtomicBool::new(false)),
+        );
+
+        let table = text_table(&[ThreadStatus::from(&register), ThreadStatus::from(&idle)]);
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(3, lines.len());
+        assert_eq!("ID  NAME      GROUP    ACTIVITY  SINCE", lines[0]);
+        assert!(lines[1].starts_with("12  worker-1  workers  working   "));
+        assert_eq!("3   main      -        (idle)    -", lines[2]);
+    }
+}
@@ -1,28 +1,43 @@
 use std::sync::atomic::AtomicBool;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
-use std::sync::Mutex;
 
+use crate::activity::ActivityState;
+use crate::inspect::ThreadInspectors;
+use crate::metrics::ThreadMetrics;
+use crate::progress::ProgressState;
 use crate::ThreadScope;
 
 /// Fake a `ThreadScope` for use in tests.
 #[derive(Default)]
 pub struct MockThreadScope {
-    activity: Arc<Mutex<Option<String>>>,
+    activity: Arc<ActivityState>,
+    inspectors: Arc<ThreadInspectors>,
+    metrics: Arc<ThreadMetrics>,
+    progress: Arc<ProgressState>,
     shutdown: Arc<AtomicBool>,
 }
 
 impl MockThreadScope {
     pub fn new() -> MockThreadScope {
         MockThreadScope {
-            activity: Arc::new(Mutex::new(None)),
+            activity: Arc::new(ActivityState::default()),
+            inspectors: Arc::new(ThreadInspectors::default()),
+            metrics: Arc::new(ThreadMetrics::default()),
+            progress: Arc::new(ProgressState::default()),
             shutdown: Arc::new(AtomicBool::new(false)),
         }
     }
 
     /// Returns a `ThreadScope` reflecting the state of this mock.
     pub fn scope(&self) -> ThreadScope {
-        ThreadScope::new(Arc::clone(&self.activity), Arc::clone(&self.shutdown))
+        ThreadScope::new(
+            Arc::clone(&self.activity),
+            Arc::clone(&self.inspectors),
+            Arc::clone(&self.metrics),
+            Arc::clone(&self.progress),
+            Arc::clone(&self.shutdown),
+        )
     }
 
     /// Set the shutdown state that `ThreadScope::should_shutdown` will return.
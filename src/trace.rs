@@ -0,0 +1,418 @@
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::collections::VecDeque;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::json;
+
+use super::registry::activity_states;
+use super::registry::current_thread_id;
+
+/// Number of activities a thread buffers before pushing them to the trace in progress.
+const THREAD_BUFFER_SIZE: usize = 64;
+
+/// Fast check to skip recording when no trace is in progress.
+static RECORDING: AtomicBool = AtomicBool::new(false);
+
+lazy_static::lazy_static! {
+    static ref RECORDER: Mutex<Option<TraceBuffer>> = Mutex::new(None);
+}
+
+/// Record the activities of all registered threads between two points in time.
+///
+/// While a trace is recorded every activity that ends is stored with the thread it ran on.
+/// Activities still in progress when the trace is stopped are included as if they ended then.
+/// Memory is bounded by keeping at most `capacity` completed activities:
+/// once the limit is reached the oldest activities are dropped.
+/// The names of exited threads are only kept while some of their activities are.
+/// Threads buffer a few completed activities and hand them to the recorder in batches
+/// so recording does not serialise activity changes across threads.
+///
+/// Traces can be exported in the Chrome Trace Event format with [`Trace::chrome_json`].
+///
+/// NOTE: activities ended from threads other than the one they were started on
+/// (for example through a cloned [`ThreadScope`]) are attributed to the ending thread.
+///
+/// [`Trace::chrome_json`]: struct.Trace.html#method.chrome_json
+/// [`ThreadScope`]: struct.ThreadScope.html
+pub struct TraceRecorder;
+
+impl TraceRecorder {
+    /// Start recording a new trace, discarding any trace already in progress.
+    pub fn start(capacity: usize) {
+        let mut recorder = RECORDER.lock().expect("global RECORDER lock poisoned");
+        *recorder = Some(TraceBuffer {
+            capacity,
+            dropped: 0,
+            events: VecDeque::with_capacity(capacity.min(1024)),
+            names: HashMap::new(),
+            started: SystemTime::now(),
+        });
+        RECORDING.store(true, Ordering::Release);
+    }
+
+    /// Stop recording and return the trace, if one was in progress.
+    pub fn stop() -> Option<Trace> {
+        {
+            let recorder = RECORDER.lock().expect("global RECORDER lock poisoned");
+            recorder.as_ref()?;
+            RECORDING.store(false, Ordering::Release);
+        }
+        // Threads no longer buffer events so collect what they buffered so far.
+        let registered = activity_states();
+        for (_, _, state) in &registered {
+            state.flush_trace();
+        }
+        let buffer = RECORDER
+            .lock()
+            .expect("global RECORDER lock poisoned")
+            .take()?;
+        let stopped = SystemTime::now();
+        let mut events: Vec<TraceEvent> = buffer.events.into_iter().collect();
+        let mut threads: BTreeMap<u64, String> = buffer.names.into_iter().collect();
+        for (id, name, state) in registered {
+            for frame in state.snapshot().frames {
+                events.push(TraceEvent {
+                    activity: frame.activity,
+                    ended: stopped,
+                    started: frame.since.max(buffer.started),
+                    thread: id,
+                });
+            }
+            threads.insert(id, name);
+        }
+        events.sort_by(|a, b| a.started.cmp(&b.started).then(b.ended.cmp(&a.ended)));
+        Some(Trace {
+            dropped: buffer.dropped,
+            events,
+            started: buffer.started,
+            stopped,
+            threads,
+        })
+    }
+}
+
+/// Activity of a thread recorded in a [`Trace`].
+///
+/// [`Trace`]: struct.Trace.html
+#[derive(Clone, PartialEq, Eq, Hash, Debug, Serialize, Deserialize)]
+pub struct TraceEvent {
+    /// Description of the activity.
+    pub activity: String,
+
+    /// Time the activity ended, or the trace stopped.
+    pub ended: SystemTime,
+
+    /// Time the activity started, or the trace started.
+    pub started: SystemTime,
+
+    /// Registry identifier of the thread that performed the activity.
+    pub thread: u64,
+}
+
+/// Activities recorded by a [`TraceRecorder`].
+///
+/// [`TraceRecorder`]: struct.TraceRecorder.html
+#[derive(Clone, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub struct Trace {
+    /// Number of activities dropped because the recorder was full.
+    pub dropped: u64,
+
+    /// Recorded activities, ordered by start time.
+    pub events: Vec<TraceEvent>,
+
+    /// Time recording started.
+    pub started: SystemTime,
+
+    /// Time recording stopped.
+    pub stopped: SystemTime,
+
+    /// Full name of the threads that performed the recorded activities.
+    pub threads: BTreeMap<u64, String>,
+}
+
+impl Trace {
+    /// Export the trace in the Chrome Trace Event JSON format.
+    ///
+    /// The result can be loaded in Perfetto or `chrome://tracing` and shows one track
+    /// per thread, named after the thread full name, with nested activities.
+    pub fn chrome_json(&self) -> String {
+        let pid = ::std::process::id();
+        // Registry ids are too large to be represented exactly in JSON so use small ones.
+        let tids: HashMap<u64, usize> = self
+            .threads
+            .keys()
+            .chain(self.events.iter().map(|event| &event.thread))
+            .fold(HashMap::new(), |mut tids, id| {
+                let next = tids.len() + 1;
+                tids.entry(*id).or_insert(next);
+                tids
+            });
+        let micros = |time: SystemTime| {
+            time.duration_since(self.started)
+                .map(|since| since.as_secs_f64() * 1_000_000.0)
+                .unwrap_or(0.0)
+        };
+
+        let mut events = vec![json!({
+            "args": {"name": "humthreads"},
+            "name": "process_name",
+            "ph": "M",
+            "pid": pid,
+        })];
+        for (id, tid) in &tids {
+            let name = self
+                .threads
+                .get(id)
+                .cloned()
+                .unwrap_or_else(|| format!("thread {}", id));
+            events.push(json!({
+                "args": {"name": name},
+                "name": "thread_name",
+                "ph": "M",
+                "pid": pid,
+                "tid": tid,
+            }));
+        }
+        for event in &self.events {
+            let start = micros(event.started);
+            events.push(json!({
+                "dur": (micros(event.ended) - start).max(0.0),
+                "name": event.activity,
+                "ph": "X",
+                "pid": pid,
+                "tid": tids[&event.thread],
+                "ts": start,
+            }));
+        }
+        json!({
+            "displayTimeUnit": "ms",
+            "traceEvents": events,
+        })
+        .to_string()
+    }
+}
+
+/// Bounded storage for the trace in progress.
+struct TraceBuffer {
+    capacity: usize,
+    dropped: u64,
+    events: VecDeque<TraceEvent>,
+    names: HashMap<u64, String>,
+    started: SystemTime,
+}
+
+/// Check if a trace is in progress.
+pub(crate) fn is_recording() -> bool {
+    RECORDING.load(Ordering::Acquire)
+}
+
+/// Activities ended by a thread, buffered so threads don't contend on the recorder.
+///
+/// Events are pushed to the recorder when the buffer is full, when the thread exits
+/// and when the trace is stopped.
+#[derive(Default)]
+pub(crate) struct ThreadTrace {
+    events: Mutex<Vec<TraceEvent>>,
+}
+
+impl ThreadTrace {
+    /// Record the end of an activity on the current thread, if a trace is in progress.
+    pub(crate) fn record(&self, activity: &str, since: SystemTime) {
+        if !is_recording() {
+            return;
+        }
+        let event = TraceEvent {
+            activity: activity.to_string(),
+            ended: SystemTime::now(),
+            started: since,
+            thread: current_thread_id(),
+        };
+        let mut events = self.lock();
+        // Stopping the trace flushes buffers after recording stops, check again while
+        // holding the buffer so the event is either flushed or not buffered at all.
+        if !is_recording() {
+            return;
+        }
+        events.push(event);
+        if events.len() < THREAD_BUFFER_SIZE {
+            return;
+        }
+        let events = ::std::mem::take(&mut *events);
+        push(events);
+    }
+
+    /// Push buffered events to the trace in progress, if any.
+    pub(crate) fn flush(&self) {
+        let events = ::std::mem::take(&mut *self.lock());
+        if !events.is_empty() {
+            push(events);
+        }
+    }
+
+    fn lock(&self) -> ::std::sync::MutexGuard<'_, Vec<TraceEvent>> {
+        self.events
+            .lock()
+            .expect("ThreadTrace::events lock poisoned")
+    }
+}
+
+/// Add events buffered by a thread to the trace in progress.
+///
+/// Events that ended before the trace started were buffered for an earlier trace
+/// and are discarded.
+fn push(events: Vec<TraceEvent>) {
+    let mut recorder = RECORDER.lock().expect("global RECORDER lock poisoned");
+    let buffer = match recorder.as_mut() {
+        Some(buffer) => buffer,
+        None => return,
+    };
+    for mut event in events {
+        if event.ended < buffer.started {
+            continue;
+        }
+        event.started = event.started.max(buffer.started);
+        if buffer.events.len() >= buffer.capacity {
+            buffer.dropped += 1;
+            if buffer.events.pop_front().is_none() {
+                continue;
+            }
+        }
+        buffer.events.push_back(event);
+    }
+}
+
+/// Remember the name of an exiting thread so it can be used in the trace in progress.
+pub(crate) fn thread_exited(id: u64, name: &str) {
+    let mut recorder = RECORDER.lock().expect("global RECORDER lock poisoned");
+    let buffer = match recorder.as_mut() {
+        Some(buffer) => buffer,
+        None => return,
+    };
+    // Names are only needed for threads with retained events.
+    if buffer.names.len() >= buffer.events.len() {
+        let used: HashSet<u64> = buffer.events.iter().map(|event| event.thread).collect();
+        buffer.names.retain(|id, _| used.contains(id));
+    }
+    if buffer.events.iter().any(|event| event.thread == id) {
+        buffer.names.insert(id, name.to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::super::Builder;
+    use super::TraceRecorder;
+
+    // The recorder is global so tests must not run concurrently.
+    #[test]
+    fn recorder() {
+        record_and_export();
+        bounded_memory();
+        no_capacity();
+    }
+
+    fn record_and_export() {
+        TraceRecorder::start(1000);
+        let thread = Builder::new("trace_record_and_export")
+            .spawn(|scope| {
+                let _pipeline = scope.scoped_activity("pipeline");
+                for stage in ["decode", "transform"] {
+                    let _stage = scope.scoped_activity(stage);
+                    ::std::thread::sleep(Duration::from_millis(5));
+                }
+                scope.static_activity("encode");
+                while !scope.should_shutdown() {
+                    ::std::thread::sleep(Duration::from_millis(5));
+                }
+            })
+            .expect("to spawn test thread");
+        ::std::thread::sleep(Duration::from_millis(50));
+        let trace = TraceRecorder::stop().expect("a trace to be recorded");
+        thread.request_shutdown();
+        thread.join().expect("the thread to stop");
+
+        let id = trace
+            .threads
+            .iter()
+            .find(|(_, name)| *name == "trace_record_and_export")
+            .map(|(id, _)| *id)
+            .expect("thread not found in the trace");
+        let activities: Vec<&str> = trace
+            .events
+            .iter()
+            .filter(|event| event.thread == id)
+            .map(|event| event.activity.as_str())
+            .collect();
+        assert_eq!(
+            vec!["pipeline", "decode", "transform", "encode"],
+            activities
+        );
+        assert!(TraceRecorder::stop().is_none());
+
+        let json: serde_json::Value =
+            serde_json::from_str(&trace.chrome_json()).expect("valid JSON trace");
+        let events = json["traceEvents"].as_array().expect("trace events");
+        let tid = events
+            .iter()
+            .find(|event| event["ph"] == "M" && event["args"]["name"] == "trace_record_and_export")
+            .map(|event| event["tid"].clone())
+            .expect("thread track not found");
+        let decode = events
+            .iter()
+            .find(|event| event["tid"] == tid && event["name"] == "decode")
+            .expect("decode event not found");
+        assert_eq!("X", decode["ph"]);
+        assert!(decode["dur"].as_f64().expect("duration") >= 5000.0);
+    }
+
+    fn bounded_memory() {
+        TraceRecorder::start(2);
+        let thread = Builder::new("trace_bounded_memory")
+            .spawn(|scope| {
+                for activity in ["first", "second", "third"] {
+                    let _activity = scope.scoped_activity(activity);
+                }
+            })
+            .expect("to spawn test thread");
+        thread.join().expect("the thread to stop");
+        let trace = TraceRecorder::stop().expect("a trace to be recorded");
+        let activities: Vec<&str> = trace
+            .events
+            .iter()
+            .map(|event| event.activity.as_str())
+            .collect();
+        assert!(trace.dropped >= 1);
+        assert!(activities.len() <= 2);
+        assert!(trace
+            .threads
+            .values()
+            .any(|name| name == "trace_bounded_memory"));
+    }
+
+    fn no_capacity() {
+        TraceRecorder::start(0);
+        let thread = Builder::new("trace_no_capacity")
+            .spawn(|scope| {
+                for activity in ["first", "second", "third"] {
+                    let _activity = scope.scoped_activity(activity);
+                }
+            })
+            .expect("to spawn test thread");
+        thread.join().expect("the thread to stop");
+        let trace = TraceRecorder::stop().expect("a trace to be recorded");
+        assert!(trace.dropped >= 3);
+        assert!(!trace
+            .threads
+            .values()
+            .any(|name| name == "trace_no_capacity"));
+    }
+}
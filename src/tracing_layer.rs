@@ -0,0 +1,252 @@
+use std::cell::RefCell;
+use std::fmt;
+
+use tracing::field::Field;
+use tracing::field::Visit;
+use tracing::span::Attributes;
+use tracing::span::Id;
+use tracing::span::Record;
+use tracing::Subscriber;
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+use super::current;
+use super::ActivityFields;
+use super::ActivityValue;
+use super::ThreadScopeActivityGuard;
+
+thread_local! {
+    /// Activities reported for spans entered on the current thread, innermost last.
+    static ENTERED: RefCell<Vec<(Id, ThreadScopeActivityGuard)>> = const { RefCell::new(Vec::new()) };
+}
+
+/// `tracing_subscriber` layer reporting entered spans as activities of registered threads.
+///
+/// When a span is entered on a registered thread an activity named after the span
+/// is pushed onto the thread's activity stack, with the span fields as activity fields.
+/// The activity is popped when the span is exited.
+/// Spans entered on threads that are not registered are ignored.
+///
+/// Fields recorded after the span is entered are reported the next time the span is entered.
+///
+/// Thread lifecycle events (`thread spawned`, `thread shutdown requested`, `thread exited`
+/// and `thread panicked`) are emitted with the `humthreads` target whenever the
+/// `with_tracing` feature is enabled, regardless of this layer being installed.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ActivityLayer;
+
+impl ActivityLayer {
+    pub fn new() -> ActivityLayer {
+        ActivityLayer
+    }
+}
+
+impl<S> Layer<S> for ActivityLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let span = match ctx.span(id) {
+            Some(span) => span,
+            None => return,
+        };
+        let mut fields = SpanFields::default();
+        attrs.record(&mut fields);
+        span.extensions_mut().insert(fields);
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let span = match ctx.span(id) {
+            Some(span) => span,
+            None => return,
+        };
+        let mut extensions = span.extensions_mut();
+        match extensions.get_mut::<SpanFields>() {
+            Some(fields) => values.record(fields),
+            None => {
+                let mut fields = SpanFields::default();
+                values.record(&mut fields);
+                extensions.insert(fields);
+            }
+        }
+    }
+
+    fn on_enter(&self, id: &Id, ctx: Context<'_, S>) {
+        let scope = match current() {
+            Some(scope) => scope,
+            None => return,
+        };
+        let span = match ctx.span(id) {
+            Some(span) => span,
+            None => return,
+        };
+        let fields = span
+            .extensions()
+            .get::<SpanFields>()
+            .map(|fields| fields.0.clone())
+            .unwrap_or_default();
+        let guard = scope.scoped_activity_with(span.name(), fields);
+        let _ = ENTERED.try_with(|entered| entered.borrow_mut().push((id.clone(), guard)));
+    }
+
+    fn on_exit(&self, id: &Id, _ctx: Context<'_, S>) {
+        // Drop the guard outside of the borrow in case popping the activity re-enters the layer.
+        let guard = ENTERED
+            .try_with(|entered| {
+                let mut entered = entered.borrow_mut();
+                let index = entered.iter().rposition(|(entered, _)| entered == id)?;
+                Some(entered.remove(index))
+            })
+            .ok()
+            .flatten();
+        drop(guard);
+    }
+}
+
+/// Span fields converted to activity fields, stored in the span extensions.
+#[derive(Default)]
+struct SpanFields(ActivityFields);
+
+impl SpanFields {
+    fn insert<V: Into<ActivityValue>>(&mut self, field: &Field, value: V) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+}
+
+impl Visit for SpanFields {
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.insert(field, value);
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.insert(field, format!("{:?}", value));
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.insert(field, value);
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.insert(field, value);
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.insert(field, value);
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        match i64::try_from(value) {
+            Ok(value) => self.insert(field, value),
+            Err(_) => self.insert(field, value.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::Mutex;
+
+    use tracing::field::Field;
+    use tracing::field::Visit;
+    use tracing::Event;
+    use tracing::Subscriber;
+    use tracing_subscriber::layer::Context;
+    use tracing_subscriber::layer::SubscriberExt;
+    use tracing_subscriber::Layer;
+    use tracing_subscriber::Registry;
+
+    use super::super::register_current_thread;
+    use super::super::registered_threads;
+    use super::super::registry::current_thread_id;
+    use super::super::request_thread_shutdown;
+    use super::super::ActivityValue;
+    use super::super::Builder;
+    use super::ActivityLayer;
+
+    /// Layer capturing the message and fields of `humthreads` events.
+    #[derive(Clone, Default)]
+    struct Capture(Arc<Mutex<Vec<String>>>);
+
+    impl<S: Subscriber> Layer<S> for Capture {
+        fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+            if event.metadata().target() != "humthreads" {
+                return;
+            }
+            let mut line = CaptureLine::default();
+            event.record(&mut line);
+            self.0.lock().unwrap().push(line.0);
+        }
+    }
+
+    #[derive(Default)]
+    struct CaptureLine(String);
+
+    impl Visit for CaptureLine {
+        fn record_debug(&mut self, field: &Field, value: &dyn ::std::fmt::Debug) {
+            if !self.0.is_empty() {
+                self.0.push(' ');
+            }
+            self.0.push_str(&format!("{}={:?}", field.name(), value));
+        }
+    }
+
+    #[test]
+    fn spans_report_activities() {
+        let activity_stack = || {
+            let id = current_thread_id();
+            registered_threads()
+                .into_iter()
+                .find(|thread| thread.id == id)
+                .map(|thread| thread.activity_stack)
+                .expect("thread not registered")
+        };
+        let thread = Builder::new("spans_report_activities")
+            .spawn(move |_| {
+                let subscriber = Registry::default().with(ActivityLayer::new());
+                tracing::subscriber::with_default(subscriber, || {
+                    let request = tracing::info_span!("handling request", request = 42);
+                    let _request = request.enter();
+                    let parse = tracing::info_span!("parsing");
+                    let stack = parse.in_scope(activity_stack);
+                    (stack, activity_stack())
+                })
+            })
+            .expect("to spawn test thread");
+        let (stack, after) = thread.join().expect("the thread to stop");
+
+        let activities: Vec<&str> = stack.iter().map(|frame| frame.activity.as_str()).collect();
+        assert_eq!(vec!["handling request", "parsing"], activities);
+        assert_eq!(
+            Some(&ActivityValue::Int(42)),
+            stack[0].fields.get("request")
+        );
+        assert_eq!(1, after.len());
+    }
+
+    #[test]
+    fn lifecycle_events() {
+        let capture = Capture::default();
+        let subscriber = Registry::default().with(capture.clone());
+        tracing::subscriber::with_default(subscriber, || {
+            let (guard, _scope) = register_current_thread("tracing_lifecycle_events")
+                .expect("to register the thread");
+            assert!(request_thread_shutdown(current_thread_id()));
+            drop(guard);
+        });
+
+        let id = current_thread_id();
+        let events = capture.0.lock().unwrap().clone();
+        let expected: Vec<String> = ["spawned", "shutdown requested", "exited"]
+            .iter()
+            .map(|transition| {
+                format!(
+                    "message=thread {} thread.id={} thread.name=\"tracing_lifecycle_events\"",
+                    transition, id
+                )
+            })
+            .collect();
+        assert_eq!(expected, events);
+    }
+}
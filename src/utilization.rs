@@ -0,0 +1,260 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+/// Length, in seconds, of each bucket in the sliding window.
+const BUCKET_SECS: u64 = 5;
+
+/// Number of buckets in the sliding window.
+const WINDOW_BUCKETS: usize = 12;
+
+/// Maximum number of distinct activities tracked for each thread.
+const MAX_ACTIVITIES: usize = 64;
+
+/// Name used to track activities once the `MAX_ACTIVITIES` limit is reached.
+const OTHER_ACTIVITIES: &str = "(other)";
+
+lazy_static::lazy_static! {
+    /// Reference point used to assign time to sliding window buckets.
+    static ref WINDOW_EPOCH: Instant = Instant::now();
+}
+
+/// Public view of how a thread spent its time.
+///
+/// A thread is busy while it reports an activity and idle otherwise.
+#[derive(Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub struct ThreadUtilization {
+    /// Time spent busy in each activity, if enabled with [`Builder::track_activity_time`].
+    ///
+    /// [`Builder::track_activity_time`]: struct.Builder.html#method.track_activity_time
+    pub activities: BTreeMap<String, Duration>,
+
+    /// Total time spent busy over the thread's lifetime.
+    pub busy: Duration,
+
+    /// Total time spent idle over the thread's lifetime.
+    pub idle: Duration,
+
+    /// Percentage of the thread's lifetime spent busy.
+    pub lifetime_percent: f64,
+
+    /// Percentage of the last minute (or the thread's lifetime, if shorter) spent busy.
+    pub window_percent: f64,
+}
+
+/// Internal accounting of busy and idle time for a thread.
+///
+/// Time is accounted every time the thread changes activity and the busy flag
+/// can be checked without locking to skip accounting when nothing changes.
+/// Memory is bounded by tracking at most `MAX_ACTIVITIES` activity names,
+/// time in additional activities is tracked together.
+pub(crate) struct Utilization {
+    busy: AtomicBool,
+    state: Mutex<UtilizationState>,
+    track_activities: bool,
+}
+
+impl Utilization {
+    pub(crate) fn new(track_activities: bool) -> Utilization {
+        lazy_static::initialize(&WINDOW_EPOCH);
+        let state = UtilizationState {
+            activities: BTreeMap::new(),
+            busy: Duration::default(),
+            current: None,
+            idle: Duration::default(),
+            is_busy: false,
+            since: Instant::now(),
+            window: [WindowBucket::default(); WINDOW_BUCKETS],
+        };
+        Utilization {
+            busy: AtomicBool::new(false),
+            state: Mutex::new(state),
+            track_activities,
+        }
+    }
+
+    /// Check if the thread is currently busy.
+    #[cfg(test)]
+    fn is_busy(&self) -> bool {
+        self.busy.load(Ordering::Relaxed)
+    }
+
+    /// Compute the utilization report.
+    pub(crate) fn snapshot(&self) -> ThreadUtilization {
+        let mut state = self
+            .state
+            .lock()
+            .expect("Utilization::state lock poisoned")
+            .clone();
+        let now = Instant::now();
+        state.account(now);
+        let lifetime_percent = percent(state.busy, state.busy + state.idle);
+        let oldest = bucket_index(now).saturating_sub(WINDOW_BUCKETS as u64 - 1);
+        let (busy, total) = state
+            .window
+            .iter()
+            .filter(|bucket| bucket.index >= oldest)
+            .fold((Duration::default(), Duration::default()), |acc, bucket| {
+                (acc.0 + bucket.busy, acc.1 + bucket.total)
+            });
+        ThreadUtilization {
+            activities: state.activities,
+            busy: state.busy,
+            idle: state.idle,
+            lifetime_percent,
+            window_percent: percent(busy, total),
+        }
+    }
+
+    /// Account time spent in the previous activity and switch to the new one.
+    ///
+    /// The activity name is only requested if activity time is tracked.
+    /// Without activity tracking, changes that keep the thread busy (or idle) are ignored
+    /// without locking.
+    pub(crate) fn transition<F>(&self, busy: bool, activity: F)
+    where
+        F: FnOnce() -> Option<String>,
+    {
+        if !self.track_activities && self.busy.load(Ordering::Relaxed) == busy {
+            return;
+        }
+        let mut state = self.state.lock().expect("Utilization::state lock poisoned");
+        state.account(Instant::now());
+        state.is_busy = busy;
+        state.current = if self.track_activities && busy {
+            activity()
+        } else {
+            None
+        };
+        self.busy.store(busy, Ordering::Relaxed);
+    }
+}
+
+#[derive(Clone)]
+struct UtilizationState {
+    activities: BTreeMap<String, Duration>,
+    busy: Duration,
+    current: Option<String>,
+    idle: Duration,
+    is_busy: bool,
+    since: Instant,
+    window: [WindowBucket; WINDOW_BUCKETS],
+}
+
+impl UtilizationState {
+    /// Account the time since the last transition to the current state.
+    fn account(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.since);
+        if self.is_busy {
+            self.busy += elapsed;
+        } else {
+            self.idle += elapsed;
+        }
+        if let Some(current) = self.current.as_ref() {
+            if let Some(total) = self.activities.get_mut(current) {
+                *total += elapsed;
+            } else {
+                let activity = if self.activities.len() >= MAX_ACTIVITIES {
+                    OTHER_ACTIVITIES.to_string()
+                } else {
+                    current.clone()
+                };
+                *self.activities.entry(activity).or_default() += elapsed;
+            }
+        }
+
+        // Split the elapsed time across window buckets.
+        let mut start = self.since;
+        while start < now {
+            let index = bucket_index(start);
+            let end = *WINDOW_EPOCH + Duration::from_secs((index + 1) * BUCKET_SECS);
+            let end = end.min(now);
+            let bucket = &mut self.window[index as usize % WINDOW_BUCKETS];
+            if bucket.index != index {
+                *bucket = WindowBucket {
+                    index,
+                    ..WindowBucket::default()
+                };
+            }
+            let elapsed = end - start;
+            bucket.total += elapsed;
+            if self.is_busy {
+                bucket.busy += elapsed;
+            }
+            start = end;
+        }
+        self.since = now;
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct WindowBucket {
+    busy: Duration,
+    index: u64,
+    total: Duration,
+}
+
+fn bucket_index(instant: Instant) -> u64 {
+    instant.saturating_duration_since(*WINDOW_EPOCH).as_secs() / BUCKET_SECS
+}
+
+fn percent(part: Duration, total: Duration) -> f64 {
+    if total.is_zero() {
+        return 0.0;
+    }
+    part.as_secs_f64() / total.as_secs_f64() * 100.0
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::Utilization;
+    use super::MAX_ACTIVITIES;
+
+    #[test]
+    fn busy_and_idle_time() {
+        let utilization = Utilization::new(true);
+        ::std::thread::sleep(Duration::from_millis(20));
+        utilization.transition(true, || Some("working".into()));
+        ::std::thread::sleep(Duration::from_millis(20));
+        utilization.transition(true, || Some("resting".into()));
+        ::std::thread::sleep(Duration::from_millis(20));
+        utilization.transition(false, || None);
+        let report = utilization.snapshot();
+
+        assert!(!utilization.is_busy());
+        assert!(report.busy >= Duration::from_millis(40));
+        assert!(report.idle >= Duration::from_millis(20));
+        assert!(report.lifetime_percent > 40.0 && report.lifetime_percent < 90.0);
+        assert!(report.window_percent > 40.0 && report.window_percent < 90.0);
+        assert!(report.activities["working"] >= Duration::from_millis(20));
+        assert!(report.activities["resting"] >= Duration::from_millis(20));
+    }
+
+    #[test]
+    fn activities_not_tracked() {
+        let utilization = Utilization::new(false);
+        utilization.transition(true, || panic!("activity name should not be requested"));
+        assert!(utilization.is_busy());
+        assert!(utilization.snapshot().activities.is_empty());
+    }
+
+    #[test]
+    fn bounded_activities() {
+        let utilization = Utilization::new(true);
+        for activity in 0..(MAX_ACTIVITIES + 10) {
+            utilization.transition(true, || Some(format!("activity {}", activity)));
+        }
+        utilization.transition(false, || None);
+        let report = utilization.snapshot();
+        assert_eq!(MAX_ACTIVITIES + 1, report.activities.len());
+        assert!(report.activities.contains_key("(other)"));
+    }
+}